@@ -1,4 +1,7 @@
-use crate::{Gb, TC_SEC};
+use crate::{
+    savestate::{StateError, StateReader, StateWriter},
+    Gb, TC_SEC,
+};
 
 const APU_TIMER_RES: u16 = ((TC_SEC / 512) & 0xFFFF) as u16;
 
@@ -269,8 +272,63 @@ impl Square1 {
         }
     }
 
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.dac_on);
+        w.write_bool(self.snd_counter);
+        w.write_u16(self.snd_len);
+        w.write_u8(self.p_half);
+        w.write_u16(self.freq);
+        w.write_u8(self.duty);
+        w.write_u8(self.duty_bit);
+        w.write_u16(self.period);
+        w.write_u8(self.out);
+        w.write_u8(self.env_vol);
+        w.write_bool(self.env_on);
+        w.write_bool(self.env_inc);
+        w.write_u8(self.env_base_vol);
+        w.write_u8(self.env_period);
+        w.write_u8(self.env_timer);
+        w.write_bool(self.sw_on);
+        w.write_bool(self.sw_dec);
+        w.write_u8(self.sw_period);
+        w.write_u8(self.sw_shift);
+        w.write_u8(self.sw_timer);
+        w.write_u16(self.sw_shadow_freq);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.on = r.read_bool()?;
+        self.dac_on = r.read_bool()?;
+        self.snd_counter = r.read_bool()?;
+        self.snd_len = r.read_u16()?;
+        self.p_half = r.read_u8()? & 1;
+        self.freq = r.read_u16()? & 0x7FF;
+        self.duty = r.read_u8()? & 3;
+        self.duty_bit = r.read_u8()? & 7;
+        self.period = r.read_u16()?;
+        self.out = r.read_u8()?;
+        self.env_vol = r.read_u8()?;
+        self.env_on = r.read_bool()?;
+        self.env_inc = r.read_bool()?;
+        self.env_base_vol = r.read_u8()?;
+        self.env_period = r.read_u8()?;
+        self.env_timer = r.read_u8()?;
+        self.sw_on = r.read_bool()?;
+        self.sw_dec = r.read_bool()?;
+        self.sw_period = r.read_u8()?;
+        self.sw_shift = r.read_u8()? & 7;
+        self.sw_timer = r.read_u8()?;
+        self.sw_shadow_freq = r.read_u16()?;
+        Ok(())
+    }
+
+    pub(crate) fn freq(&self) -> u16 {
+        self.freq
+    }
+
     pub(crate) fn read_nr10(&self) -> u8 {
-        0x80 | ((self.sw_period as u8 & 7) << 4) | (u8::from(!self.sw_dec) << 3) | self.sw_shift
+        0x80 | ((self.sw_period as u8 & 7) << 4) | (u8::from(self.sw_dec) << 3) | self.sw_shift
     }
 
     pub(crate) fn read_nr11(&self) -> u8 {
@@ -278,7 +336,7 @@ impl Square1 {
     }
 
     pub(crate) fn read_nr12(&self) -> u8 {
-        self.env_base_vol << 4 | u8::from(self.env_inc) | self.env_period & 7
+        self.env_base_vol << 4 | u8::from(self.env_inc) << 3 | self.env_period & 7
     }
 
     pub(crate) fn read_nr14(&self) -> u8 {
@@ -499,12 +557,55 @@ impl Square2 {
         self.env_on = false;
     }
 
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.dac_on);
+        w.write_bool(self.snd_counter);
+        w.write_u16(self.snd_len);
+        w.write_u8(self.p_half);
+        w.write_u16(self.freq);
+        w.write_u8(self.duty);
+        w.write_u8(self.duty_bit);
+        w.write_u16(self.period);
+        w.write_u8(self.out);
+        w.write_u8(self.env_vol);
+        w.write_bool(self.env_on);
+        w.write_bool(self.env_inc);
+        w.write_u8(self.env_base_vol);
+        w.write_u8(self.env_period);
+        w.write_u8(self.env_timer);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.on = r.read_bool()?;
+        self.dac_on = r.read_bool()?;
+        self.snd_counter = r.read_bool()?;
+        self.snd_len = r.read_u16()?;
+        self.p_half = r.read_u8()? & 1;
+        self.freq = r.read_u16()? & 0x7FF;
+        self.duty = r.read_u8()? & 3;
+        self.duty_bit = r.read_u8()? & 7;
+        self.period = r.read_u16()?;
+        self.out = r.read_u8()?;
+        self.env_vol = r.read_u8()?;
+        self.env_on = r.read_bool()?;
+        self.env_inc = r.read_bool()?;
+        self.env_base_vol = r.read_u8()?;
+        self.env_period = r.read_u8()?;
+        self.env_timer = r.read_u8()?;
+        Ok(())
+    }
+
+    pub(crate) fn freq(&self) -> u16 {
+        self.freq
+    }
+
     pub(crate) fn read_nr21(&self) -> u8 {
         0x3F | ((self.duty as u8) << 6)
     }
 
     pub(crate) fn read_nr22(&self) -> u8 {
-        self.env_base_vol << 4 | u8::from(self.env_inc) | self.env_period & 7
+        self.env_base_vol << 4 | u8::from(self.env_inc) << 3 | self.env_period & 7
     }
 
     pub(crate) fn read_nr24(&self) -> u8 {
@@ -684,6 +785,48 @@ impl Wave {
         self.nr30 = 0;
     }
 
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.dac_on);
+        w.write_bool(self.use_len);
+        w.write_u16(self.snd_len);
+        w.write_u8(self.p_half);
+        w.write_u16(self.freq);
+        w.write_u16(self.period);
+        w.write_u8(self.sample_buffer);
+        w.write_bytes(&self.ram);
+        w.write_u8(self.sample_idx);
+        w.write_u8(self.vol);
+        w.write_u8(self.nr30);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.on = r.read_bool()?;
+        self.dac_on = r.read_bool()?;
+        self.use_len = r.read_bool()?;
+        self.snd_len = r.read_u16()?;
+        self.p_half = r.read_u8()? & 1;
+        self.freq = r.read_u16()? & 0x7FF;
+        self.period = r.read_u16()?;
+        self.sample_buffer = r.read_u8()?;
+        r.read_bytes(&mut self.ram)?;
+        self.sample_idx = r.read_u8()? % WAV_SAMPLE_SIZE;
+        self.vol = r.read_u8()? & 3;
+        self.nr30 = r.read_u8()?;
+
+        // samples are derived from wave RAM
+        for (i, val) in self.ram.iter().enumerate() {
+            self.samples[i * 2] = val >> 4;
+            self.samples[i * 2 + 1] = val & 0xF;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn freq(&self) -> u16 {
+        self.freq
+    }
+
     pub(crate) fn read_wave_ram(&self, addr: u8) -> u8 {
         let index = (addr - 0x30) as usize;
         self.ram[index]
@@ -860,8 +1003,49 @@ impl Noise {
         self.nr43 = 0;
     }
 
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.dac_on);
+        w.write_bool(self.snd_counter);
+        w.write_u16(self.snd_len);
+        w.write_u8(self.p_half);
+        w.write_u16(self.timer);
+        w.write_u16(self.timer_period);
+        w.write_u16(self.lfsr);
+        w.write_bool(self.wide_step);
+        w.write_u8(self.out);
+        w.write_u8(self.nr43);
+        w.write_bool(self.env_on);
+        w.write_u8(self.env_vol);
+        w.write_bool(self.env_inc);
+        w.write_u8(self.env_base_vol);
+        w.write_u8(self.env_period);
+        w.write_u8(self.env_timer);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.on = r.read_bool()?;
+        self.dac_on = r.read_bool()?;
+        self.snd_counter = r.read_bool()?;
+        self.snd_len = r.read_u16()?;
+        self.p_half = r.read_u8()? & 1;
+        self.timer = r.read_u16()?;
+        self.timer_period = r.read_u16()?;
+        self.lfsr = r.read_u16()?;
+        self.wide_step = r.read_bool()?;
+        self.out = r.read_u8()?;
+        self.nr43 = r.read_u8()?;
+        self.env_on = r.read_bool()?;
+        self.env_vol = r.read_u8()?;
+        self.env_inc = r.read_bool()?;
+        self.env_base_vol = r.read_u8()?;
+        self.env_period = r.read_u8()?;
+        self.env_timer = r.read_u8()?;
+        Ok(())
+    }
+
     pub(crate) fn read_nr42(&self) -> u8 {
-        self.env_base_vol << 4 | u8::from(self.env_inc) | self.env_period & 7
+        self.env_base_vol << 4 | u8::from(self.env_inc) << 3 | self.env_period & 7
     }
 
     pub(crate) fn read_nr43(&self) -> u8 {
@@ -1011,3 +1195,32 @@ impl Noise {
         self.p_half = p_half;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Noise, Square1, Square2};
+
+    // bit 3 of NR10 is set to decrease the frequency and
+    // bit 3 of NRx2 to increase the volume, they read back
+    // as written
+    #[test]
+    fn direction_bits_read_back() {
+        let mut ch1 = Square1::default();
+        let mut ch2 = Square2::default();
+        let mut ch4 = Noise::default();
+
+        for val in [0x7F, 0x77] {
+            ch1.write_nr10(val);
+            assert_eq!(ch1.read_nr10(), 0x80 | val);
+        }
+
+        for val in [0xF8, 0xF3, 0x0B, 0x04] {
+            ch1.write_nr12(val);
+            ch2.write_nr22(val);
+            ch4.write_nr42(val);
+            assert_eq!(ch1.read_nr12(), val);
+            assert_eq!(ch2.read_nr22(), val);
+            assert_eq!(ch4.read_nr42(), val);
+        }
+    }
+}
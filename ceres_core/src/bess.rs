@@ -0,0 +1,587 @@
+// Best Effort Save State, the format used to exchange
+// states with SameBoy and other emulators:
+// https://github.com/LIJI32/SameBoy/blob/master/BESS.md
+
+use crate::{
+    memory::{
+        HdmaState, BCPS, BGP, DIV, DMA, HDMA1, HDMA2, HDMA3, HDMA4, HDMA5, IF, KEY0, KEY1, LCDC,
+        LY, LYC, NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24, NR30, NR31, NR32, NR33,
+        NR34, NR41, NR42, NR43, NR44, NR50, NR51, NR52, OBP0, OBP1, OCPS, OPRI, P1, SB, SC, SCX,
+        SCY, STAT, SVBK, TAC, TIMA, TMA, VBK, WAV_BEGIN, WAV_END, WX, WY,
+    },
+    ppu::{OAM_SIZE, VRAM_SIZE_CGB},
+    rtc::RTC_TRAILER_SIZE,
+    savestate::{StateError, StateReader, StateWriter},
+    CompatMode, Gb, Model, HRAM_SIZE, KEY1_SPEED_B, KEY1_SWITCH_B, WRAM_SIZE,
+};
+
+const BESS_MAGIC: [u8; 4] = *b"BESS";
+const BESS_NAME: &str = concat!("Ceres ", env!("CARGO_PKG_VERSION"));
+const CORE_MAJOR: u16 = 1;
+const CORE_MINOR: u16 = 1;
+const CORE_LEN: usize = 0xD0;
+const INFO_LEN: usize = 0x12;
+const IO_SIZE: usize = 0x80;
+const BOOT: u8 = 0x50;
+
+// OAM and HRAM as seen by the CPU, the rest of our buffers
+// is unused
+const OAM_USED: usize = 0xA0;
+const HRAM_USED: usize = HRAM_SIZE - 1;
+
+// number of memory dumps referenced by the CORE block:
+// WRAM, VRAM, MBC RAM, OAM, HRAM, BG and OBJ palettes
+const DUMPS: usize = 7;
+
+// sizes and offsets always fit in 32 bits
+#[allow(clippy::cast_possible_truncation)]
+fn write_u32_usize(w: &mut StateWriter, val: usize) {
+    w.write_u32(val as u32);
+}
+
+fn write_block_header(w: &mut StateWriter, id: [u8; 4], len: usize) {
+    w.write_bytes(&id);
+    write_u32_usize(w, len);
+}
+
+// blocks we know how to load
+struct Blocks<'a> {
+    core: &'a [u8],
+    xoam: Option<&'a [u8]>,
+    mbc: Option<&'a [u8]>,
+    rtc: Option<&'a [u8]>,
+}
+
+fn copy_prefix(dst: &mut [u8], src: &[u8]) {
+    let len = dst.len().min(src.len());
+    dst[..len].copy_from_slice(&src[..len]);
+}
+
+impl Gb {
+    /// Returns the size in bytes of a BESS save state of
+    /// this ``GameBoy``.
+    #[must_use]
+    pub fn bess_size(&self) -> usize {
+        let mut w = StateWriter::new(&mut []);
+        self.write_bess(&mut w, 0);
+        w.pos()
+    }
+
+    /// Writes a BESS (Best Effort Save State) into `buf`
+    /// and returns the number of bytes written. This
+    /// format can be loaded by other emulators like
+    /// `SameBoy`. `unix_time` is the current time in
+    /// seconds, stored with the cartridge clock.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `buf` is smaller than
+    /// `bess_size`.
+    pub fn save_bess(&self, buf: &mut [u8], unix_time: u64) -> Result<usize, StateError> {
+        if buf.len() < self.bess_size() {
+            return Err(StateError::BufferTooSmall);
+        }
+
+        let mut w = StateWriter::new(buf);
+        self.write_bess(&mut w, unix_time);
+        Ok(w.pos())
+    }
+
+    /// Loads a BESS (Best Effort Save State) made by Ceres
+    /// or other emulators. Unknown blocks are ignored
+    /// and state not covered by the format is left
+    /// untouched. The emulator is left untouched on
+    /// error. The cartridge clock catches up with the time
+    /// passed since the state was saved on the next call
+    /// to `catch_up_cartridge_rtc`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the state is corrupt, has an
+    /// unsupported major version or belongs to another ROM.
+    pub fn load_bess(&mut self, buf: &[u8]) -> Result<(), StateError> {
+        let footer = buf.len().checked_sub(8).ok_or(StateError::InvalidMagic)?;
+        if buf[footer + 4..] != BESS_MAGIC {
+            return Err(StateError::InvalidMagic);
+        }
+
+        let Blocks {
+            core,
+            xoam,
+            mbc,
+            rtc,
+        } = self.bess_blocks(buf, footer)?;
+
+        let mut r = StateReader::new(core);
+
+        if r.read_u16()? != CORE_MAJOR {
+            return Err(StateError::UnsupportedVersion);
+        }
+        // minor versions are backwards compatible
+        r.read_u16()?;
+
+        let mut model = [0; 4];
+        r.read_bytes(&mut model)?;
+        let model = match model {
+            [b'G', b'M', ..] => Model::Mgb,
+            [b'G' | b'S', ..] => Model::Dmg,
+            [b'C', ..] => Model::Cgb,
+            _ => return Err(StateError::InvalidValue),
+        };
+
+        let pc = r.read_u16()?;
+        let af = r.read_u16()?;
+        let bc = r.read_u16()?;
+        let de = r.read_u16()?;
+        let hl = r.read_u16()?;
+        let sp = r.read_u16()?;
+        let ime = r.read_u8()?;
+        let ie = r.read_u8()?;
+        let exec_state = r.read_u8()?;
+        r.read_u8()?;
+
+        let mut io = [0; IO_SIZE];
+        r.read_bytes(&mut io)?;
+
+        let mut dumps: [&[u8]; DUMPS] = [&[]; DUMPS];
+        for dump in &mut dumps {
+            let size = r.read_u32()? as usize;
+            let offset = r.read_u32()? as usize;
+            *dump = buf
+                .get(offset..offset + size)
+                .ok_or(StateError::InvalidSize)?;
+        }
+
+        // everything is validated, start modifying the state
+        self.model = model;
+        self.compat_mode = match model {
+            Model::Dmg | Model::Mgb => CompatMode::Dmg,
+            Model::Cgb if io[KEY0 as usize] == 4 => CompatMode::Compat,
+            Model::Cgb => CompatMode::Cgb,
+        };
+        self.boot_rom = (io[BOOT as usize] == 0).then_some(model.boot_rom());
+
+        self.pc = pc;
+        self.af = af & 0xFFF0;
+        self.bc = bc;
+        self.de = de;
+        self.hl = hl;
+        self.sp = sp;
+        self.ime = ime != 0;
+        self.ie = ie;
+        self.cpu_halted = exec_state != 0;
+        self.cpu_ei_delay = false;
+        self.halt_bug = false;
+        self.stolen_cycles = 0;
+
+        let [wram, vram, cart_ram, oam, hram, bcp, ocp] = dumps;
+        copy_prefix(&mut self.wram, wram);
+        copy_prefix(&mut self.vram, vram);
+        copy_prefix(self.cart.mut_ram(), cart_ram);
+        copy_prefix(&mut self.oam[..OAM_USED], oam);
+        copy_prefix(&mut self.hram[..HRAM_USED], hram);
+        self.bcp.set_raw(bcp);
+        self.ocp.set_raw(ocp);
+
+        if let Some(xoam) = xoam {
+            copy_prefix(&mut self.oam[OAM_USED..], xoam);
+        }
+
+        if let Some(mbc) = mbc {
+            self.cart.unlock_mbc();
+            for write in mbc.chunks_exact(3) {
+                let addr = u16::from_le_bytes([write[0], write[1]]);
+                if addr <= 0x7FFF {
+                    self.cart.write_rom(addr, write[2]);
+                }
+            }
+        }
+
+        if let Some(rtc) = rtc {
+            self.cart.set_mbc3_rtc(rtc);
+        }
+
+        self.load_bess_io(&io);
+
+        Ok(())
+    }
+
+    fn bess_blocks<'a>(&self, buf: &'a [u8], footer: usize) -> Result<Blocks<'a>, StateError> {
+        let mut r = StateReader::new(buf);
+        r.seek(footer);
+        let first_block = r.read_u32()? as usize;
+        r.seek(first_block);
+
+        let mut core = None;
+        let mut xoam = None;
+        let mut mbc = None;
+        let mut rtc = None;
+
+        loop {
+            let mut id = [0; 4];
+            r.read_bytes(&mut id)?;
+            let len = r.read_u32()? as usize;
+            let data = buf
+                .get(r.pos()..r.pos() + len)
+                .ok_or(StateError::InvalidSize)?;
+            r.seek(r.pos() + len);
+
+            match &id {
+                b"END " => break,
+                b"INFO" => {
+                    if len != INFO_LEN || data[0x10..] != self.cart.global_checksum() {
+                        return Err(StateError::RomMismatch);
+                    }
+                }
+                b"CORE" => core = Some(data),
+                b"XOAM" => xoam = Some(data),
+                b"MBC " => mbc = Some(data),
+                b"RTC " => rtc = Some(data),
+                _ => (),
+            }
+        }
+
+        let core = core.ok_or(StateError::InvalidValue)?;
+
+        if mbc.is_some_and(|mbc| mbc.len() % 3 != 0)
+            || rtc.is_some_and(|rtc| rtc.len() != RTC_TRAILER_SIZE)
+        {
+            return Err(StateError::InvalidSize);
+        }
+
+        Ok(Blocks {
+            core,
+            xoam,
+            mbc,
+            rtc,
+        })
+    }
+
+    fn write_bess(&self, w: &mut StateWriter, unix_time: u64) {
+        let cgb = self.model == Model::Cgb;
+        let (bcp, ocp) = (self.bcp.raw(), self.ocp.raw());
+
+        let dumps: [&[u8]; DUMPS] = if cgb {
+            [
+                &self.wram,
+                &self.vram,
+                self.cart.ram(),
+                &self.oam[..OAM_USED],
+                &self.hram[..HRAM_USED],
+                &bcp,
+                &ocp,
+            ]
+        } else {
+            [
+                &self.wram[..WRAM_SIZE],
+                &self.vram[..VRAM_SIZE_CGB / 2],
+                self.cart.ram(),
+                &self.oam[..OAM_USED],
+                &self.hram[..HRAM_USED],
+                &[],
+                &[],
+            ]
+        };
+
+        // raw memory first, blocks reference it by offset
+        let mut dump_offsets = [0; DUMPS];
+        for (offset, dump) in dump_offsets.iter_mut().zip(dumps) {
+            *offset = w.pos();
+            w.write_bytes(dump);
+        }
+
+        let first_block = w.pos();
+
+        write_block_header(w, *b"NAME", BESS_NAME.len());
+        w.write_bytes(BESS_NAME.as_bytes());
+
+        write_block_header(w, *b"INFO", INFO_LEN);
+        w.write_bytes(self.cart.title());
+        w.write_bytes(&self.cart.global_checksum());
+
+        write_block_header(w, *b"CORE", CORE_LEN);
+        w.write_u16(CORE_MAJOR);
+        w.write_u16(CORE_MINOR);
+        w.write_bytes(match self.model {
+            Model::Dmg => b"GDB ",
+            Model::Mgb => b"GM  ",
+            Model::Cgb => b"CCE ",
+        });
+        w.write_u16(self.pc);
+        w.write_u16(self.af);
+        w.write_u16(self.bc);
+        w.write_u16(self.de);
+        w.write_u16(self.hl);
+        w.write_u16(self.sp);
+        w.write_bool(self.ime);
+        w.write_u8(self.ie);
+        w.write_bool(self.cpu_halted);
+        w.write_u8(0);
+        w.write_bytes(&self.bess_io());
+        for (offset, dump) in dump_offsets.iter().zip(dumps) {
+            write_u32_usize(w, dump.len());
+            write_u32_usize(w, *offset);
+        }
+
+        write_block_header(w, *b"XOAM", OAM_SIZE - OAM_USED);
+        w.write_bytes(&self.oam[OAM_USED..]);
+
+        let mut mbc_writes = 0;
+        self.cart.for_each_mbc_write(|_, _| mbc_writes += 1);
+        if mbc_writes > 0 {
+            write_block_header(w, *b"MBC ", mbc_writes * 3);
+            self.cart.for_each_mbc_write(|addr, val| {
+                w.write_u16(addr);
+                w.write_u8(val);
+            });
+        }
+
+        // the same format as the clock at the end of saves
+        if let Some(rtc) = self.cart.mbc3_rtc(unix_time) {
+            write_block_header(w, *b"RTC ", rtc.len());
+            w.write_bytes(&rtc);
+        }
+
+        write_block_header(w, *b"END ", 0);
+
+        write_u32_usize(w, first_block);
+        w.write_bytes(&BESS_MAGIC);
+    }
+
+    // IO registers as read by the CPU, except for write only
+    // registers, which hold their last written value
+    fn bess_io(&self) -> [u8; IO_SIZE] {
+        let mut io = [0; IO_SIZE];
+        for (addr, reg) in (0..).zip(io.iter_mut()) {
+            *reg = self.read_high(addr);
+        }
+
+        let freq_regs = [
+            (NR13, NR14, self.apu_ch1.freq()),
+            (NR23, NR24, self.apu_ch2.freq()),
+            (NR33, NR34, self.apu_ch3.freq()),
+        ];
+
+        for (lo, hi, freq) in freq_regs {
+            io[lo as usize] = (freq & 0xFF) as u8;
+            io[hi as usize] = io[hi as usize] & 0x40 | (freq >> 8) as u8;
+        }
+
+        if self.model == Model::Cgb {
+            io[KEY0 as usize] = match self.compat_mode {
+                CompatMode::Compat => 4,
+                CompatMode::Dmg | CompatMode::Cgb => 0x80,
+            };
+            io[HDMA1 as usize] = (self.hdma_src >> 8) as u8;
+            io[HDMA2 as usize] = (self.hdma_src & 0xFF) as u8;
+            io[HDMA3 as usize] = (self.hdma_dst >> 8) as u8;
+            io[HDMA4 as usize] = (self.hdma_dst & 0xFF) as u8;
+        }
+
+        io[BOOT as usize] = u8::from(self.boot_rom.is_none());
+
+        io
+    }
+
+    fn load_bess_io(&mut self, io: &[u8; IO_SIZE]) {
+        let reg = |addr: u8| io[addr as usize];
+
+        self.write_joy(reg(P1));
+        self.sb = reg(SB);
+        self.sc = reg(SC) & 0x83;
+        // restart pending transfers, with no partner
+        self.serial_bits = if self.sc & 0x81 == 0x81 { 8 } else { 0 };
+        self.serial_in = 0xFF;
+        self.system_clk = u16::from(reg(DIV)) << 8;
+        self.tima = reg(TIMA);
+        self.tma = reg(TMA);
+        self.tac = reg(TAC) & 7;
+        self.tac_enable = reg(TAC) & 4 != 0;
+        self.ifr = reg(IF) & 0x1F;
+
+        // apu, registers are ignored while it's off
+        self.write_nr52(reg(NR52));
+        self.write_nr50(reg(NR50));
+        self.write_nr51(reg(NR51));
+        for addr in WAV_BEGIN..=WAV_END {
+            self.apu_ch3.write_wave_ram(addr, reg(addr));
+        }
+
+        if self.apu_on {
+            // channels that were playing are triggered again
+            let trigger = |ch: u8| if reg(NR52) & ch != 0 { 0x80 } else { 0 };
+
+            self.apu_ch1.write_nr10(reg(NR10));
+            self.apu_ch1.write_nr11(reg(NR11));
+            self.apu_ch1.write_nr12(reg(NR12));
+            self.apu_ch1.write_nr13(reg(NR13));
+            self.apu_ch1.write_nr14(reg(NR14) & 0x47 | trigger(1));
+            self.apu_ch2.write_nr21(reg(NR21));
+            self.apu_ch2.write_nr22(reg(NR22));
+            self.apu_ch2.write_nr23(reg(NR23));
+            self.apu_ch2.write_nr24(reg(NR24) & 0x47 | trigger(2));
+            self.apu_ch3.write_nr30(reg(NR30));
+            self.apu_ch3.write_nr31(reg(NR31));
+            self.apu_ch3.write_nr32(reg(NR32));
+            self.apu_ch3.write_nr33(reg(NR33));
+            self.apu_ch3.write_nr34(reg(NR34) & 0x47 | trigger(4));
+            self.apu_ch4.write_nr41(reg(NR41));
+            self.apu_ch4.write_nr42(reg(NR42));
+            self.apu_ch4.write_nr43(reg(NR43));
+            self.apu_ch4.write_nr44(reg(NR44) & 0x40 | trigger(8));
+        }
+
+        // ppu
+        self.lcdc = reg(LCDC);
+        self.stat = reg(STAT) & 0x7F;
+        self.scy = reg(SCY);
+        self.scx = reg(SCX);
+        self.ly = reg(LY);
+        self.lyc = reg(LYC);
+        self.dma = reg(DMA);
+        self.dma_on = false;
+        self.bgp = reg(BGP);
+        self.obp0 = reg(OBP0);
+        self.obp1 = reg(OBP1);
+        self.wy = reg(WY);
+        self.wx = reg(WX);
+        self.resync_ppu();
+
+        if self.model == Model::Cgb {
+            self.key1 = reg(KEY1) & (KEY1_SPEED_B | KEY1_SWITCH_B);
+            self.double_speed = self.key1 & KEY1_SPEED_B != 0;
+            self.vbk = reg(VBK) & 1;
+            self.hdma_src = u16::from(reg(HDMA1)) << 8 | u16::from(reg(HDMA2) & 0xF0);
+            self.hdma_dst = u16::from(reg(HDMA3) & 0x1F) << 8 | u16::from(reg(HDMA4) & 0xF0);
+            self.hdma5 = reg(HDMA5) & 0x7F;
+            // active on low
+            if reg(HDMA5) & 0x80 == 0 {
+                self.hdma_state = HdmaState::HBlank;
+                self.hdma_len = (u16::from(self.hdma5) + 1) * 0x10;
+            } else {
+                self.hdma_state = HdmaState::Sleep;
+                self.hdma_len = 0;
+            }
+            self.bcp.set_spec(reg(BCPS));
+            self.ocp.set_spec(reg(OCPS));
+            self.opri = reg(OPRI);
+            self.svbk = reg(SVBK) & 7;
+            self.svbk_true = self.svbk.max(1);
+        } else {
+            self.key1 = 0;
+            self.double_speed = false;
+            self.vbk = 0;
+            self.hdma_state = HdmaState::Sleep;
+            self.svbk = 0;
+            self.svbk_true = 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{Cartridge, Gb, Model, WRAM_SIZE},
+        alloc::{vec, vec::Vec},
+    };
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // inc a; jr -3
+        rom[0x100..0x103].copy_from_slice(&[0x3C, 0x18, 0xFD]);
+        rom[0x134..0x139].copy_from_slice(b"CERES");
+        // MBC1 + RAM + battery, 32 KiB ROM, 8 KiB RAM
+        rom[0x147] = 0x03;
+        rom[0x148] = 0x00;
+        rom[0x149] = 0x02;
+        rom[0x14E] = 0x12;
+        rom[0x14F] = 0x34;
+        rom
+    }
+
+    fn cart(rom: &[u8]) -> Cartridge {
+        Cartridge::new(rom, None).unwrap()
+    }
+
+    fn export(gb: &Gb) -> Vec<u8> {
+        let mut buf = vec![0; gb.bess_size()];
+        gb.save_bess(&mut buf, 0).unwrap();
+        buf
+    }
+
+    #[test]
+    fn bess_round_trip() {
+        for model in [Model::Dmg, Model::Mgb, Model::Cgb] {
+            let mut gb = Gb::new(model, cart(&rom()), |_, _| (), 48000);
+            for _ in 0..30 {
+                gb.run_frame();
+            }
+
+            gb.wram
+                .iter_mut()
+                .zip((0..=u8::MAX).cycle())
+                .for_each(|(b, i)| *b = i);
+            gb.cart.mut_ram().fill(0xAB);
+            gb.cart.write_rom(0x0000, 0x0A);
+
+            let state = export(&gb);
+
+            let mut other = Gb::new(model, cart(&rom()), |_, _| (), 48000);
+            other.load_bess(&state).unwrap();
+
+            assert_eq!(state, export(&other));
+            assert_eq!(gb.pc, other.pc);
+            assert_eq!(gb.af, other.af);
+            assert_eq!(gb.wram[..WRAM_SIZE], other.wram[..WRAM_SIZE]);
+            assert_eq!(gb.cart.ram(), other.cart.ram());
+            assert_eq!(other.cart.read_ram(0xA000), 0xAB);
+        }
+    }
+
+    #[test]
+    fn bess_rtc_round_trip() {
+        let mut rom = rom();
+        // MBC3 + timer + RAM + battery
+        rom[0x147] = 0x10;
+        let mut gb = Gb::new(Model::Cgb, cart(&rom), |_, _| (), 48000);
+
+        // 5 minutes and 3 seconds on day 0x123
+        gb.cart.write_rom(0x0000, 0x0A);
+        for (reg, val) in [(0x08, 3), (0x09, 5), (0x0A, 0), (0x0B, 0x23), (0x0C, 1)] {
+            gb.cart.write_rom(0x4000, reg);
+            gb.cart.write_ram(0xA000, val);
+        }
+
+        let mut state = vec![0; gb.bess_size()];
+        gb.save_bess(&mut state, 1000).unwrap();
+
+        let mut other = Gb::new(Model::Cgb, cart(&rom), |_, _| (), 48000);
+        other.load_bess(&state).unwrap();
+        // a minute passed since the state was saved
+        other.catch_up_cartridge_rtc(1060);
+
+        other.cart.write_rom(0x6000, 0);
+        other.cart.write_rom(0x6000, 1);
+        let mut read = |reg: u8| {
+            other.cart.write_rom(0x4000, reg);
+            other.cart.read_ram(0xA000)
+        };
+        assert_eq!(read(0x08), 3);
+        assert_eq!(read(0x09), 6);
+        assert_eq!(read(0x0B), 0x23);
+        assert_eq!(read(0x0C) & 1, 1);
+    }
+
+    #[test]
+    fn bess_rejects_other_rom() {
+        let gb = Gb::new(Model::Cgb, cart(&rom()), |_, _| (), 48000);
+        let state = export(&gb);
+
+        let mut rom = rom();
+        rom[0x14F] = 0x35;
+        let mut other = Gb::new(Model::Cgb, cart(&rom), |_, _| (), 48000);
+
+        assert!(other.load_bess(&state).is_err());
+        assert!(other.load_bess(&state[..state.len() - 1]).is_err());
+    }
+}
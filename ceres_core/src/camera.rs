@@ -0,0 +1,370 @@
+use {
+    crate::savestate::{StateError, StateReader, StateWriter},
+    alloc::{boxed::Box, vec::Vec},
+};
+
+/// Width in pixels of the pictures taken by the Pocket
+/// Camera.
+pub const CAMERA_WIDTH: usize = 128;
+/// Height in pixels of the pictures taken by the Pocket
+/// Camera.
+pub const CAMERA_HEIGHT: usize = 112;
+
+const REGISTERS: usize = 0x36;
+const DITHER_START: usize = 6;
+// the picture is written as 16x14 tiles at this offset of
+// RAM bank 0
+const IMAGE_START: usize = 0x100;
+
+const CAPTURE_START_B: u8 = 1;
+const EXPOSURE_TIMING_B: u8 = 0x80;
+
+// edge enhancement ratio in quarters, 50% to 500%
+const EDGE_RATIOS: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+
+/// Where the Pocket Camera takes its pictures from, like a
+/// webcam or an image file.
+pub trait ImageSource {
+    /// Called when the game takes a picture. Fills `pixels`
+    /// with `CAMERA_WIDTH` x `CAMERA_HEIGHT` brightness
+    /// values in row major order, from 0 (black) to 255
+    /// (white).
+    fn capture(&mut self, pixels: &mut [u8]);
+}
+
+/// An `ImageSource` that always sees the same picture.
+pub struct StillImage {
+    pixels: Vec<u8>,
+}
+
+impl StillImage {
+    /// Scales an image of `width` x `height` brightness
+    /// values in row major order to the camera, cropping
+    /// the sides that don't fit. An image smaller than
+    /// `width` x `height` is seen as black.
+    #[must_use]
+    pub fn new(width: usize, height: usize, gray: &[u8]) -> Self {
+        let mut pixels = alloc::vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+
+        if width == 0 || height == 0 || gray.len() < width * height {
+            return Self { pixels };
+        }
+
+        // the centered part with the aspect of the camera
+        let (crop_w, crop_h) = if width * CAMERA_HEIGHT > height * CAMERA_WIDTH {
+            ((height * CAMERA_WIDTH / CAMERA_HEIGHT).max(1), height)
+        } else {
+            (width, (width * CAMERA_HEIGHT / CAMERA_WIDTH).max(1))
+        };
+        let left = (width - crop_w) / 2;
+        let top = (height - crop_h) / 2;
+
+        // every pixel is the average of the area it covers
+        let span = |i: usize, len: usize, crop: usize| {
+            let start = i * crop / len;
+            let end = ((i + 1) * crop / len).max(start + 1);
+            start..end
+        };
+
+        for (y, row) in pixels.chunks_exact_mut(CAMERA_WIDTH).enumerate() {
+            let ys = span(y, CAMERA_HEIGHT, crop_h);
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let xs = span(x, CAMERA_WIDTH, crop_w);
+                let mut sum = 0;
+                for sy in ys.clone() {
+                    let line = (top + sy) * width + left;
+                    sum += gray[line + xs.start..line + xs.end]
+                        .iter()
+                        .map(|&v| usize::from(v))
+                        .sum::<usize>();
+                }
+                let count = ys.len() * xs.len();
+                *pixel = u8::try_from(sum / count).unwrap_or(u8::MAX);
+            }
+        }
+
+        Self { pixels }
+    }
+}
+
+impl ImageSource for StillImage {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        pixels.copy_from_slice(&self.pixels);
+    }
+}
+
+/// The sensor of the Pocket Camera and its registers,
+/// mapped at 0xA000 when RAM bank 0x10 is selected.
+pub(crate) struct Camera {
+    regs: [u8; REGISTERS],
+    // normal speed t-cycles until the picture is taken
+    busy: u32,
+    source: Option<Box<dyn ImageSource>>,
+    sensor: Vec<u8>,
+}
+
+impl Camera {
+    pub(crate) fn new() -> Self {
+        Self {
+            regs: [0; REGISTERS],
+            busy: 0,
+            source: None,
+            sensor: alloc::vec![0; CAMERA_WIDTH * CAMERA_HEIGHT],
+        }
+    }
+
+    pub(crate) fn set_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = Some(source);
+    }
+
+    pub(crate) fn busy(&self) -> bool {
+        self.busy > 0
+    }
+
+    // only the first register can be read
+    pub(crate) fn read(&self, addr: u16) -> u8 {
+        if addr & 0x7F == 0 {
+            self.regs[0]
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, val: u8) {
+        let reg = usize::from(addr & 0x7F);
+
+        match reg {
+            0 => {
+                self.regs[0] = val & 7;
+                if val & CAPTURE_START_B != 0 && self.busy == 0 {
+                    self.busy = self.capture_cycles();
+                } else if val & CAPTURE_START_B == 0 {
+                    // stops the capture
+                    self.busy = 0;
+                }
+            }
+            1..REGISTERS => self.regs[reg] = val,
+            _ => (),
+        }
+    }
+
+    fn exposure(&self) -> u32 {
+        u32::from(u16::from_be_bytes([self.regs[2], self.regs[3]]))
+    }
+
+    // Pictures take 32446 M-cycles plus 16 per unit of
+    // exposure time, and 512 more unless the register 1
+    // bit 7 is set.
+    fn capture_cycles(&self) -> u32 {
+        let extra = if self.regs[1] & EXPOSURE_TIMING_B == 0 {
+            512
+        } else {
+            0
+        };
+        (32446 + extra + 16 * self.exposure()) * 4
+    }
+
+    // returns true if a picture was written to `ram`
+    pub(crate) fn run(&mut self, cycles: u32, ram: &mut [u8]) -> bool {
+        if self.busy == 0 {
+            return false;
+        }
+
+        self.busy = self.busy.saturating_sub(cycles);
+        if self.busy > 0 {
+            return false;
+        }
+
+        self.regs[0] &= !CAPTURE_START_B;
+        self.take_picture(ram);
+        true
+    }
+
+    fn take_picture(&mut self, ram: &mut [u8]) {
+        // without a source the lens sees nothing
+        match &mut self.source {
+            Some(source) => source.capture(&mut self.sensor),
+            None => self.sensor.fill(0),
+        }
+
+        if ram.len() < IMAGE_START + CAMERA_WIDTH * CAMERA_HEIGHT / 4 {
+            return;
+        }
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let shade = self.dither(x, y, self.process(x, y));
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let i = IMAGE_START + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                ram[i] = ram[i] & !(1 << bit) | (shade & 1) << bit;
+                ram[i + 1] = ram[i + 1] & !(1 << bit) | (shade >> 1) << bit;
+            }
+        }
+    }
+
+    // the light seen by the sensor, amplified by the
+    // exposure time and the gain
+    fn light(&self, x: usize, y: usize) -> i32 {
+        let x = x.min(CAMERA_WIDTH - 1);
+        let y = y.min(CAMERA_HEIGHT - 1);
+        let gain = u32::from(self.regs[1] & 0x1F);
+
+        let value = u32::from(self.sensor[y * CAMERA_WIDTH + x]) * self.exposure() / 0x1000;
+        let value = value * (8 + gain) / 8;
+        i32::try_from(value.min(0xFF)).unwrap_or(0xFF)
+    }
+
+    // Register 1 bits 5 and 6 select edge enhancement,
+    // horizontal, vertical or both, register 4 bits 4 to 6
+    // its strength. The bias voltages in registers 4 and 5
+    // only tune the analog output and aren't emulated.
+    fn process(&self, x: usize, y: usize) -> i32 {
+        let value = self.light(x, y);
+        let left = self.light(x.saturating_sub(1), y);
+        let right = self.light(x + 1, y);
+        let up = self.light(x, y.saturating_sub(1));
+        let down = self.light(x, y + 1);
+
+        let edge = match (self.regs[1] >> 5) & 3 {
+            1 => 2 * value - left - right,
+            2 => 2 * value - up - down,
+            3 => 4 * value - left - right - up - down,
+            _ => 0,
+        };
+        let ratio = EDGE_RATIOS[usize::from((self.regs[4] >> 4) & 7)];
+
+        (value + edge * ratio / 4).clamp(0, 0xFF)
+    }
+
+    // Registers 6 to 0x35 are a 4x4 matrix of three
+    // thresholds, values below the first one are black and
+    // values over the last one white.
+    fn dither(&self, x: usize, y: usize, value: i32) -> u8 {
+        let i = DITHER_START + ((y % 4) * 4 + x % 4) * 3;
+        let thresholds = &self.regs[i..i + 3];
+
+        match thresholds.iter().position(|&t| value < i32::from(t)) {
+            Some(0) => 3,
+            Some(1) => 2,
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.regs);
+        w.write_u32(self.busy);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.regs)?;
+        self.busy = r.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Camera, StillImage, CAMERA_HEIGHT, CAMERA_WIDTH, IMAGE_START},
+        alloc::{boxed::Box, vec::Vec},
+    };
+
+    // the pixels seen in an image of `width` x `height` with
+    // the brightness `f(x, y)`
+    fn still(width: usize, height: usize, f: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        let gray: Vec<u8> = (0..width * height)
+            .map(|i| f(i % width, i / width))
+            .collect();
+        StillImage::new(width, height, &gray).pixels
+    }
+
+    #[test]
+    fn still_image_crops_the_sides() {
+        // wider than the camera, the sides are cut
+        let wide = still(
+            256,
+            112,
+            |x, _| if (64..192).contains(&x) { 200 } else { 0 },
+        );
+        assert!(wide.iter().all(|&p| p == 200));
+
+        // taller than the camera, the top and bottom are cut
+        let tall = still(128, 224, |_, y| if (56..168).contains(&y) { 77 } else { 0 });
+        assert!(tall.iter().all(|&p| p == 77));
+    }
+
+    #[test]
+    fn still_image_averages_when_scaling() {
+        // every 2x2 block is 0, 20, 40 and 60
+        let halved = still(2 * CAMERA_WIDTH, 2 * CAMERA_HEIGHT, |x, y| {
+            u8::try_from(x % 2 * 20 + y % 2 * 40).unwrap()
+        });
+        assert!(halved.iter().all(|&p| p == 30));
+
+        // scaled up, every pixel is repeated
+        let doubled = still(CAMERA_WIDTH / 2, CAMERA_HEIGHT / 2, |x, _| {
+            u8::try_from(x).unwrap()
+        });
+        for row in doubled.chunks_exact(CAMERA_WIDTH) {
+            assert!(row
+                .iter()
+                .enumerate()
+                .all(|(x, &p)| usize::from(p) == x / 2));
+        }
+
+        // too short for its size
+        let short = StillImage::new(CAMERA_WIDTH, CAMERA_HEIGHT, &[0xFF; 100]);
+        assert!(short.pixels.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn registers() {
+        let mut camera = Camera::new();
+        let mut ram = alloc::vec![0x55; 0x2000];
+
+        // no extra cycles, exposure 0x1000
+        camera.write(0xA001, 0x80);
+        camera.write(0xA002, 0x10);
+        camera.write(0xA003, 0x00);
+        // thresholds between black and white
+        for (reg, threshold) in (0xA006..0xA036).zip([0x40, 0x80, 0xC0].into_iter().cycle()) {
+            camera.write(reg, threshold);
+        }
+        // only register 0 reads back, mirrored every 0x80
+        assert_eq!(camera.read(0xA001), 0);
+        camera.write(0xA000, 0xFF);
+        assert_eq!(camera.read(0xA000), 7);
+        assert_eq!(camera.read(0xA080), 7);
+        assert!(camera.busy());
+
+        // a white picture after 32446 + 16 * 0x1000 M-cycles
+        camera.set_source(Box::new(StillImage::new(1, 1, &[0xFF])));
+        let cycles = (32446 + 16 * 0x1000) * 4;
+        assert!(!camera.run(cycles - 1, &mut ram));
+        assert!(camera.run(1, &mut ram));
+        assert!(!camera.busy());
+        assert_eq!(camera.read(0xA000), 6);
+        assert!(ram[..IMAGE_START].iter().all(|&b| b == 0x55));
+        assert!(ram[IMAGE_START..IMAGE_START + 0xE00]
+            .iter()
+            .all(|&b| b == 0));
+        assert!(ram[IMAGE_START + 0xE00..].iter().all(|&b| b == 0x55));
+
+        // clearing bit 0 stops a capture
+        camera.write(0xA000, 1);
+        camera.write(0xA000, 0);
+        assert!(!camera.busy());
+        assert!(!camera.run(cycles, &mut ram));
+
+        // black without a source
+        camera.source = None;
+        camera.write(0xA000, 1);
+        assert!(camera.run(cycles, &mut ram));
+        assert!(ram[IMAGE_START..IMAGE_START + 0xE00]
+            .iter()
+            .all(|&b| b == 0xFF));
+    }
+}
@@ -1,20 +1,44 @@
-use Mbc::{Mbc1, Mbc2, Mbc3, Mbc5, None};
-
-const ROM_BANK_SIZE: usize = 0x4000;
-const RAM_BANK_SIZE: usize = 0x2000;
-
-const ROM_SIZE: usize = ROMSize::Mb8.size_bytes();
-const RAM_SIZE: usize = RAMSize::Kb128.total_size_in_bytes();
-
-type Rom = [u8; ROM_SIZE];
-type Ram = [u8; RAM_SIZE];
-
+use {
+    crate::{
+        camera::ImageSource,
+        mapper::Mapper,
+        mbc::{self, Banks, MapperExt, ROM_BANK_SIZE},
+        mbc6::FLASH_SIZE,
+        mbc7::EEPROM_SIZE,
+        rtc::RTC_TRAILER_SIZE,
+        savestate::{StateError, StateReader, StateWriter},
+        tama5::TAMA5_RAM_SIZE,
+    },
+    alloc::{boxed::Box, vec::Vec},
+    Mbc::{Camera, Huc1, Huc3, Mbc1, Mbc2, Mbc3, Mbc5, Mbc6, Mbc7, Mmm01, None, Tama5},
+};
+
+// 512 half bytes built into MBC2, mirrored through
+// 0xA000-0xBFFF whatever the header says
+const MBC2_RAM_SIZE: usize = 0x200;
+// the header ends at 0x14F
+const HEADER_END: usize = 0x150;
+const LOGO_START: usize = 0x104;
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// the MBCs of the cartridge types in ROM headers
 enum Mbc {
     None,
     Mbc1,
     Mbc2,
     Mbc3,
     Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    Camera,
+    Tama5,
+    Huc1,
+    Huc3,
 }
 
 /// Represents a cartridge initialization error.
@@ -24,70 +48,161 @@ pub enum InitializationError {
     InvalidRamSize,
     NonAsciiTitleString,
     UnsupportedMBC,
+    /// The ROM length doesn't match the size in its header.
+    RomSizeMismatch,
+    /// The save is shorter than the RAM size in the ROM
+    /// header.
+    RamSizeMismatch,
 }
 
+/// A cartridge with its ROM and RAM sized as stated in the
+/// ROM header, mapped by a built-in MBC or a custom
+/// `Mapper`.
 pub struct Cartridge {
-    mbc: Mbc,
+    mapper: Box<dyn MapperExt>,
+    // identifies the ROM in save states
+    header: Box<[u8]>,
+    has_battery: bool,
+    // MBC3 clocks are exchanged through the RTC block of
+    // BESS
+    mbc3_rtc: bool,
+}
 
-    rom: Rom,
-    ram: Ram,
+impl Cartridge {
+    /// Creates a cartridge from the contents of a ROM file
+    /// and optionally a save file with the contents of
+    /// its RAM. Saves of cartridges with a clock may end
+    /// with the clock in the format used by VBA and
+    /// BGB, other saves longer than the RAM are truncated.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the ROM header contains some
+    /// invalid or unsupported value, if the length of `rom`
+    /// doesn't match the size in the header or if `ram` is
+    /// shorter than the RAM.
+    pub fn new(rom: &[u8], ram: Option<&[u8]>) -> Result<Self, InitializationError> {
+        Self::load(rom, ram, Option::None)
+    }
 
-    rom_bank_lo: u8,
-    rom_bank_hi: u8,
-    rom_offsets: (usize, usize),
-    // bit mask of rom bank, anded with the rom bank selected gets
-    // the actual rom bank depending on the ROM size
-    rom_bank_mask: usize,
+    /// Like `new`, but the MBC is the one of
+    /// `cartridge_type` instead of the one in the ROM
+    /// header, byte 0x147.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the ROM header contains some
+    /// invalid value, if `cartridge_type` isn't supported,
+    /// if the length of `rom` doesn't match the size in the
+    /// header or if `ram` is shorter than the RAM.
+    pub fn with_cartridge_type(
+        rom: &[u8],
+        ram: Option<&[u8]>,
+        cartridge_type: u8,
+    ) -> Result<Self, InitializationError> {
+        Self::load(rom, ram, Some(cartridge_type))
+    }
 
-    ram_enabled: bool,
-    ram_bank: u8,
-    ram_offset: usize,
+    /// Creates a cartridge mapped by `mapper`, whatever the
+    /// type in its ROM header. The header is read through
+    /// the mapper and the cartridge has a battery if the
+    /// mapper has save data.
+    #[must_use]
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Self {
+        let header = (0..0x150).map(|addr| mapper.read_rom(addr)).collect();
+
+        Self {
+            has_battery: !mapper.save_data().is_empty(),
+            mapper: Box::new(mbc::Custom::new(mapper)),
+            header,
+            mbc3_rtc: false,
+        }
+    }
 
-    mbc1_bank_mode: bool,
-    has_battery: bool,
-    has_ram: bool,
+    fn load(
+        rom: &[u8],
+        ram: Option<&[u8]>,
+        cartridge_type: Option<u8>,
+    ) -> Result<Self, InitializationError> {
+        if rom.len() < HEADER_END {
+            return Err(InitializationError::RomSizeMismatch);
+        }
 
-    mbc30: bool,
-    mbc1_multicart: bool,
-}
+        // MMM01 multicarts may start with the header of their
+        // first game, the menu's is in the last 32 KiB
+        let header = match rom.len().checked_sub(2 * ROM_BANK_SIZE) {
+            Some(menu) if !is_mmm01(rom) && is_mmm01_menu(&rom[menu..]) => &rom[menu..],
+            _ => rom,
+        };
 
-impl Cartridge {
-    pub fn init(&mut self) -> Result<(), InitializationError> {
-        let rom = &mut self.rom;
-
-        let rom_size = ROMSize::new(rom)?;
-        let ram_size = RAMSize::new(rom)?;
-        let mbc30 = ram_size.num_banks() >= 8;
-        let rom_bank_mask = rom_size.bank_bit_mask();
-        let has_ram = ram_size != RAMSize::None;
-
-        let (mbc, has_battery) = match rom[0x147] {
-            0x00 => (None, false),
-            0x01 | 0x02 => (Mbc1, false),
-            0x03 => (Mbc1, true),
-            0x05 => (Mbc2, false),
-            0x06 => (Mbc2, true),
-            0x0F | 0x10 | 0x13 => (Mbc3, true),
-            0x11 | 0x12 => (Mbc3, false),
-            0x19 | 0x1A | 0x1C | 0x1D => (Mbc5, false),
-            0x1B | 0x1E => (Mbc5, true),
-            _ => return Err(InitializationError::UnsupportedMBC),
+        let rom_size = ROMSize::new(header)?;
+        let ram_size = RAMSize::new(header)?;
+
+        if rom.len() != rom_size.size_bytes() {
+            return Err(InitializationError::RomSizeMismatch);
+        }
+
+        let cartridge_type = cartridge_type.unwrap_or(header[0x147]);
+        let (mbc, has_battery) = mbc_and_battery(cartridge_type)?;
+
+        // The EEPROM is saved as RAM and the flash after it,
+        // both start erased to ones from `erased` on.
+        let sram_len = ram_size.total_size_in_bytes();
+        let (ram_len, erased) = match mbc {
+            Mbc7 => (EEPROM_SIZE, 0),
+            Tama5 => (TAMA5_RAM_SIZE, TAMA5_RAM_SIZE),
+            Mbc6 => (sram_len + FLASH_SIZE, sram_len),
+            Mbc2 => (MBC2_RAM_SIZE, MBC2_RAM_SIZE),
+            _ => (sram_len, sram_len),
         };
 
-        let rom_offsets = (0x0000, 0x4000);
-        let ram_offset = 0;
+        let (ram, trailer): (Box<[u8]>, &[u8]) = match ram {
+            // Saves of cartridges with a clock may end with it,
+            // older versions saved the whole 128 KiB buffer
+            // whatever the RAM size, the rest is dropped.
+            Some(ram) if ram.len() >= ram_len => {
+                let (ram, trailer) = ram.split_at(ram_len);
+                (ram.into(), trailer)
+            }
+            Some(_) => return Err(InitializationError::RamSizeMismatch),
+            Option::None => {
+                let mut blank = alloc::vec![0; ram_len];
+                blank[erased..].fill(0xFF);
+                (blank.into(), &[])
+            }
+        };
 
-        self.mbc = mbc;
-        self.has_battery = has_battery;
-        self.has_ram = has_ram;
-        self.rom_offsets = rom_offsets;
-        self.ram_offset = ram_offset;
-        self.mbc30 = mbc30;
+        let has_rtc = matches!(cartridge_type, 0x0F | 0x10);
+        let banks = Banks::new(rom.into(), ram);
+        let mut mapper: Box<dyn MapperExt> = match mbc {
+            None => Box::new(mbc::NoMbc::new(banks)),
+            Mbc1 => Box::new(mbc::Mbc1::new(banks, is_mbc1_multicart(rom))),
+            Mbc2 => Box::new(mbc::Mbc2::new(banks)),
+            Mbc3 => Box::new(mbc::Mbc3::new(banks, ram_size.num_banks() >= 8, has_rtc)),
+            Mbc5 => {
+                let has_rumble = matches!(cartridge_type, 0x1C..=0x1E);
+                Box::new(mbc::Mbc5::new(banks, rom_size.bank_bit_mask(), has_rumble))
+            }
+            Mbc6 => Box::new(mbc::Mbc6::new(banks)),
+            Mbc7 => Box::new(mbc::Mbc7::new(banks)),
+            Mmm01 => Box::new(mbc::Mmm01::new(banks)),
+            Camera => Box::new(mbc::PocketCamera::new(banks)),
+            Tama5 => Box::new(mbc::Tama5::new(banks)),
+            Huc1 => Box::new(mbc::Huc1::new(banks)),
+            Huc3 => Box::new(mbc::Huc3::new(banks)),
+        };
 
-        self.rom_bank_lo = 1;
-        self.rom_bank_mask = rom_bank_mask;
+        if !trailer.is_empty() {
+            mapper.set_clock_data(trailer);
+            mapper.clear_save_dirty();
+        }
 
-        Ok(())
+        Ok(Self {
+            mapper,
+            header: rom[..HEADER_END].into(),
+            has_battery,
+            mbc3_rtc: has_rtc,
+        })
     }
 
     #[must_use]
@@ -95,197 +210,205 @@ impl Cartridge {
         self.has_battery
     }
 
+    /// Returns true if the cartridge is an MBC1 multicart,
+    /// detected from the headers of its games.
     #[must_use]
-    pub fn read_rom(&self, addr: u16) -> u8 {
-        let bank_addr = match addr {
-            0x0000..=0x3FFF => {
-                let (rom_lower, _) = self.rom_offsets;
-                rom_lower as usize | (addr as usize & 0x3FFF)
-            }
-            0x4000..=0x7FFF => {
-                let (_, rom_upper) = self.rom_offsets;
-                rom_upper as usize | (addr as usize & 0x3FFF)
-            }
-            _ => 0,
-        };
+    pub fn mbc1_multicart(&self) -> bool {
+        self.mapper.multicart()
+    }
 
-        self.rom[bank_addr as usize]
+    /// Overrides the detection of MBC1 multicarts, which
+    /// wire the ROM bank with 4 bits instead of 5. Other
+    /// cartridges ignore it.
+    pub fn set_mbc1_multicart(&mut self, multicart: bool) {
+        self.mapper.set_multicart(multicart);
+    }
+
+    pub(crate) fn read_rom(&self, addr: u16) -> u8 {
+        self.mapper.read_rom(addr)
+    }
+
+    pub(crate) fn write_rom(&mut self, addr: u16, val: u8) {
+        self.mapper.write_rom(addr, val);
+    }
+
+    pub(crate) fn read_ram(&self, addr: u16) -> u8 {
+        self.mapper.read_ram(addr)
+    }
+
+    pub(crate) fn write_ram(&mut self, addr: u16, val: u8) {
+        self.mapper.write_ram(addr, val);
+    }
+
+    // runs the clocks and the camera for `cycles` normal
+    // speed t-cycles
+    pub(crate) fn run(&mut self, cycles: u32) {
+        self.mapper.run(cycles);
     }
 
     #[must_use]
-    fn ram_addr(&self, addr: u16) -> usize {
-        self.ram_offset | (addr as usize & 0x1FFF)
+    pub(crate) fn save_dirty(&self) -> bool {
+        self.mapper.save_dirty()
     }
 
-    fn mbc_read_ram(&self, ram_enabled: bool, addr: u16) -> u8 {
-        if self.has_ram && ram_enabled {
-            let addr = self.ram_addr(addr);
-            self.ram[addr]
-        } else {
-            0xFF
-        }
+    pub(crate) fn clear_save_dirty(&mut self) {
+        self.mapper.clear_save_dirty();
+    }
+
+    pub(crate) fn catch_up_rtc(&mut self, unix_time: u64) {
+        self.mapper.catch_up(unix_time);
+    }
+
+    #[must_use]
+    pub(crate) fn rtc_trailer(&self, unix_time: u64) -> Option<Vec<u8>> {
+        self.mapper.clock_data(unix_time)
     }
 
+    // the MBC3 clock in the format of VBA and BGB, which is
+    // also the one of the RTC block of BESS
     #[must_use]
-    pub fn read_ram(&self, addr: u16) -> u8 {
-        match self.mbc {
-            None => 0xFF,
-            Mbc1 | Mbc5 => self.mbc_read_ram(self.ram_enabled, addr),
-            Mbc2 => (self.mbc_read_ram(self.ram_enabled, addr) & 0xF) | 0xF0,
-            Mbc3 => match self.ram_bank {
-                0x00..=0x03 => self.mbc_read_ram(self.ram_enabled, addr),
-                0x04..=0x07 => self.mbc_read_ram(self.ram_enabled && self.mbc30, addr),
-                _ => 0xFF,
-            },
+    pub(crate) fn mbc3_rtc(&self, unix_time: u64) -> Option<Vec<u8>> {
+        self.mbc3_rtc
+            .then(|| self.mapper.clock_data(unix_time))
+            .flatten()
+            .filter(|rtc| rtc.len() == RTC_TRAILER_SIZE)
+    }
+
+    pub(crate) fn set_mbc3_rtc(&mut self, rtc_data: &[u8]) {
+        if self.mbc3_rtc {
+            self.mapper.set_clock_data(rtc_data);
         }
     }
 
-    fn mbc1_rom_offsets(&self) -> (usize, usize) {
-        let upper_bits = if self.mbc1_multicart {
-            self.rom_bank_hi << 4
-        } else {
-            self.rom_bank_hi << 5
-        };
-        let lower_bits = if self.mbc1_multicart {
-            self.rom_bank_lo & 0xF
-        } else {
-            self.rom_bank_lo
-        };
+    // header checksum and global checksum, used to identify
+    // the ROM
+    #[must_use]
+    pub(crate) fn header_checksum(&self) -> [u8; 3] {
+        [self.header[0x14D], self.header[0x14E], self.header[0x14F]]
+    }
 
-        let lower_bank = if self.mbc1_bank_mode {
-            upper_bits as usize
-        } else {
-            0
-        };
-        let upper_bank = (upper_bits | lower_bits) as usize;
-        (ROM_BANK_SIZE * lower_bank, ROM_BANK_SIZE * upper_bank)
+    #[must_use]
+    pub(crate) fn title(&self) -> &[u8] {
+        &self.header[0x134..0x144]
     }
 
-    fn mbc1_ram_offset(&self) -> usize {
-        let bank = if self.mbc1_bank_mode {
-            self.rom_bank_hi as usize
-        } else {
-            0
-        };
-        RAM_BANK_SIZE * bank
-    }
-
-    fn mbc5_rom_offsets(&self) -> (usize, usize) {
-        let lower_bits = self.rom_bank_lo as usize;
-        let upper_bits = (self.rom_bank_hi as usize) << 8;
-        let rom_bank = (upper_bits | lower_bits) & self.rom_bank_mask;
-        // let rom_bank = if rom_bank == 0 { 1 } else { rom_bank };
-        (0x0000, ROM_BANK_SIZE * rom_bank)
-    }
-
-    pub fn write_rom(&mut self, addr: u16, val: u8) {
-        match self.mbc {
-            None => (),
-            Mbc1 => match addr {
-                0x0000..=0x1FFF => self.ram_enabled = (val & 0xF) == 0xA,
-                0x2000..=0x3FFF => {
-                    let val = val & 0x1F;
-                    self.rom_bank_lo = if val == 0 { 1 } else { val };
-                    self.rom_offsets = self.mbc1_rom_offsets();
-                }
-                0x4000..=0x5FFF => {
-                    self.rom_bank_hi = val & 3;
-                    self.rom_offsets = self.mbc1_rom_offsets();
-                    self.ram_offset = self.mbc1_ram_offset();
-                }
-                0x6000..=0x7FFF => {
-                    self.mbc1_bank_mode = val & 1 != 0;
-                    self.rom_offsets = self.mbc1_rom_offsets();
-                    self.ram_offset = self.mbc1_ram_offset();
-                }
-                _ => (),
-            },
-            Mbc2 => {
-                if addr <= 0x3FFF {
-                    if (addr >> 8) & 1 == 0 {
-                        self.ram_enabled = (val & 0xF) == 0xA;
-                    } else {
-                        let val = val & 0xF;
-                        self.rom_bank_lo = if val == 0 { 1 } else { val };
-                        self.rom_offsets = (0x0000, ROM_BANK_SIZE * self.rom_bank_lo as usize);
-                    }
-                }
-            }
-            Mbc3 => match addr {
-                0x0000..=0x1FFF => self.ram_enabled = (val & 0x0F) == 0x0A,
-                0x2000..=0x3FFF => {
-                    self.rom_bank_lo = if val == 0 { 1 } else { val & 0x7F };
-                    self.rom_offsets = (0x0000, ROM_BANK_SIZE * self.rom_bank_lo as usize);
-                }
-                0x4000..=0x5FFF => {
-                    self.ram_bank = val & 0x7;
-                    if self.mbc30 {
-                        self.ram_offset = RAM_BANK_SIZE * self.ram_bank as usize;
-                    } else {
-                        self.ram_offset = RAM_BANK_SIZE * (self.ram_bank & 0x3) as usize;
-                    }
-                }
-
-                _ => (),
-            },
-            Mbc5 => match addr {
-                0x0000..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
-                0x2000..=0x2FFF => {
-                    self.rom_bank_lo = val;
-                    self.rom_offsets = self.mbc5_rom_offsets();
-                }
-                0x3000..=0x3FFF => {
-                    self.rom_bank_hi = val & 1;
-                    self.rom_offsets = self.mbc5_rom_offsets();
-                }
-                0x4000..=0x5FFF => {
-                    self.ram_bank = val & 0xF;
-                    self.ram_offset = RAM_BANK_SIZE * self.ram_bank as usize;
-                }
-                _ => (),
-            },
-        }
+    #[must_use]
+    pub(crate) fn global_checksum(&self) -> [u8; 2] {
+        [self.header[0x14E], self.header[0x14F]]
     }
 
-    fn mbc_write_ram(&mut self, ram_enabled: bool, addr: u16, val: u8) {
-        if self.has_ram && ram_enabled {
-            let addr = self.ram_addr(addr);
-            self.ram[addr] = val;
-        }
+    // calls `f` with the sequence of MBC register writes that
+    // rebuild the current banking state
+    pub(crate) fn for_each_mbc_write(&self, mut f: impl FnMut(u16, u8)) {
+        self.mapper.for_each_register_write(&mut f);
     }
 
-    pub fn write_ram(&mut self, addr: u16, val: u8) {
-        match self.mbc {
-            None => (),
-            Mbc1 | Mbc2 | Mbc5 => {
-                self.mbc_write_ram(self.ram_enabled, addr, val);
-            }
-            Mbc3 => match self.ram_bank {
-                0x00..=0x03 => self.mbc_write_ram(self.ram_enabled, addr, val),
-                0x04..=0x07 => self.mbc_write_ram(self.ram_enabled && self.mbc30, addr, val),
-                _ => (),
-            },
-        }
+    // MMM01 registers can't be written once mapped, they are
+    // reset before replaying writes
+    pub(crate) fn unlock_mbc(&mut self) {
+        self.mapper.reset_registers();
+    }
+
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        let mut state = alloc::vec![0; self.mapper.state_size()];
+        self.mapper.save_state(&mut state);
+        w.write_bytes(self.mapper.save_data());
+        w.write_bytes(&state);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut state = alloc::vec![0; self.mapper.state_size()];
+        r.read_bytes(self.mapper.save_data_mut())?;
+        r.read_bytes(&mut state)?;
+        self.mapper.load_state(&state)
     }
 
     #[must_use]
     pub fn ram(&self) -> &[u8] {
-        &self.ram
+        self.mapper.save_data()
     }
 
     #[must_use]
     pub fn mut_ram(&mut self) -> &mut [u8] {
-        &mut self.ram
+        self.mapper.save_data_mut()
+    }
+
+    pub(crate) fn set_accelerometer(&mut self, x: i32, y: i32) {
+        self.mapper.set_accelerometer(x, y);
+    }
+
+    pub(crate) fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mapper.set_camera_source(source);
     }
 
     #[must_use]
-    pub fn mut_rom(&mut self) -> &mut [u8] {
-        &mut self.rom
+    pub(crate) fn rumble(&self) -> bool {
+        self.mapper.rumble()
     }
 }
 
-#[derive(PartialEq, Eq)]
-enum ROMSize {
+// the MBC of a cartridge type and if it has a battery
+fn mbc_and_battery(cartridge_type: u8) -> Result<(Mbc, bool), InitializationError> {
+    Ok(match cartridge_type {
+        0x00 | 0x08 => (None, false),
+        0x09 => (None, true),
+        0x01 | 0x02 => (Mbc1, false),
+        0x03 => (Mbc1, true),
+        0x05 => (Mbc2, false),
+        0x06 => (Mbc2, true),
+        0x0B | 0x0C => (Mmm01, false),
+        0x0D => (Mmm01, true),
+        0x0F | 0x10 | 0x13 => (Mbc3, true),
+        0x11 | 0x12 => (Mbc3, false),
+        0x19 | 0x1A | 0x1C | 0x1D => (Mbc5, false),
+        0x1B | 0x1E => (Mbc5, true),
+        0x20 => (Mbc6, true),
+        0x22 => (Mbc7, true),
+        0xFC => (Camera, true),
+        0xFD => (Tama5, true),
+        0xFE => (Huc3, true),
+        0xFF => (Huc1, true),
+        _ => return Err(InitializationError::UnsupportedMBC),
+    })
+}
+
+// MBC1 multicarts are 1 MiB and every game starts with its
+// own header, games after the menu at banks 0x10, 0x20 and
+// 0x30
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x10_0000 {
+        return false;
+    }
+
+    let games = [0x10, 0x20, 0x30]
+        .into_iter()
+        .filter(|bank| {
+            let logo = bank * ROM_BANK_SIZE + LOGO_START;
+            rom[logo..logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+        })
+        .count();
+
+    games >= 2
+}
+
+fn is_mmm01(header: &[u8]) -> bool {
+    matches!(header[0x147], 0x0B..=0x0D)
+}
+
+// the last 32 KiB of other cartridges may have anything at
+// 0x147, the menu also needs a valid header
+fn is_mmm01_menu(header: &[u8]) -> bool {
+    let checksum = header[0x134..0x14D]
+        .iter()
+        .fold(0_u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+
+    is_mmm01(header)
+        && header[LOGO_START..LOGO_START + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+        && header[0x14D] == checksum
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ROMSize {
     Kb32  = 0,
     Kb64  = 1,
     Kb128 = 2,
@@ -298,7 +421,7 @@ enum ROMSize {
 }
 
 impl ROMSize {
-    const fn new(rom: &Rom) -> Result<Self, InitializationError> {
+    pub(crate) const fn new(rom: &[u8]) -> Result<Self, InitializationError> {
         use ROMSize::{Kb128, Kb256, Kb32, Kb512, Kb64, Mb1, Mb2, Mb4, Mb8};
         let rom_size_byte = rom[0x148];
         let rom_size = match rom_size_byte {
@@ -318,7 +441,7 @@ impl ROMSize {
     }
 
     // total size in  bytes
-    const fn size_bytes(self) -> usize {
+    pub(crate) const fn size_bytes(self) -> usize {
         let kib_32 = 1 << 15;
         kib_32 << (self as usize)
     }
@@ -329,7 +452,7 @@ impl ROMSize {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
-enum RAMSize {
+pub(crate) enum RAMSize {
     None,
     Kb2,
     Kb8,
@@ -339,7 +462,7 @@ enum RAMSize {
 }
 
 impl RAMSize {
-    const fn new(rom: &Rom) -> Result<Self, InitializationError> {
+    pub(crate) const fn new(rom: &[u8]) -> Result<Self, InitializationError> {
         use RAMSize::{Kb128, Kb2, Kb32, Kb64, Kb8, None};
         let ram_size_byte = rom[0x149];
         let ram_size = match ram_size_byte {
@@ -355,7 +478,7 @@ impl RAMSize {
         Ok(ram_size)
     }
 
-    const fn total_size_in_bytes(self) -> usize {
+    pub(crate) const fn total_size_in_bytes(self) -> usize {
         self.num_banks() as usize * self.bank_size_in_bytes() as usize
     }
 
@@ -378,3 +501,173 @@ impl RAMSize {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            is_mbc1_multicart, Cartridge, LOGO_START, MBC2_RAM_SIZE, NINTENDO_LOGO, ROM_BANK_SIZE,
+        },
+        alloc::vec::Vec,
+    };
+
+    // 32 KiB ROM with no code
+    fn rom(cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = alloc::vec![0; 0x8000];
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        rom
+    }
+
+    fn mbc2() -> Cartridge {
+        let mut cart = Cartridge::new(&rom(0x06, 0), None).unwrap();
+        // A8 clear selects the RAM enable register
+        cart.write_rom(0x0000, 0x0A);
+        cart
+    }
+
+    #[test]
+    fn mbc2_save_size() {
+        let cart = mbc2();
+        assert_eq!(cart.ram().len(), MBC2_RAM_SIZE);
+
+        let save = alloc::vec![0x0F; MBC2_RAM_SIZE];
+        assert!(Cartridge::new(&rom(0x06, 0), Some(&save)).is_ok());
+    }
+
+    #[test]
+    fn mbc2_stores_nibbles() {
+        let mut cart = mbc2();
+
+        for val in [0x00, 0x05, 0xA7, 0xFF] {
+            cart.write_ram(0xA000, val);
+            assert_eq!(cart.read_ram(0xA000), 0xF0 | (val & 0xF));
+            assert_eq!(cart.ram()[0], val & 0xF);
+        }
+    }
+
+    #[test]
+    fn mbc2_ram_mirrors() {
+        let mut cart = mbc2();
+        cart.write_ram(0xA001, 0x3);
+
+        for addr in [0xA001, 0xA201, 0xB001, 0xBE01] {
+            assert_eq!(cart.read_ram(addr), 0xF3, "{addr:#06X}");
+        }
+
+        cart.write_ram(0xBFFF, 0x9);
+        assert_eq!(cart.read_ram(0xA1FF), 0xF9);
+    }
+
+    #[test]
+    fn rom_ram() {
+        for (cartridge_type, has_battery) in [(0x08, false), (0x09, true)] {
+            // 8 KiB RAM, enabled without an MBC
+            let mut cart = Cartridge::new(&rom(cartridge_type, 0x02), None).unwrap();
+            assert_eq!(cart.has_battery(), has_battery);
+            assert_eq!(cart.ram().len(), 0x2000);
+
+            cart.write_ram(0xA000, 0x12);
+            cart.write_ram(0xBFFF, 0x34);
+            assert_eq!(cart.read_ram(0xA000), 0x12);
+            assert_eq!(cart.read_ram(0xBFFF), 0x34);
+            assert_eq!(cart.ram()[0x1FFF], 0x34);
+
+            // banking writes don't move the RAM
+            cart.write_rom(0x4000, 0x01);
+            assert_eq!(cart.read_ram(0xA000), 0x12);
+        }
+    }
+
+    // 64 KiB MBC1 ROM, its last 32 KiB start at 0x8000
+    fn mmm01_rom(menu_type: u8) -> Vec<u8> {
+        let mut rom = alloc::vec![0; 0x1_0000];
+        rom[0x0000] = 0x11;
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        rom[0x8000] = 0x22;
+        rom[0x8147] = menu_type;
+        rom[0x8148] = 0x01;
+        rom
+    }
+
+    #[test]
+    fn stray_mmm01_type_is_ignored() {
+        let rom = mmm01_rom(0x0B);
+        let cart = Cartridge::new(&rom, None).unwrap();
+        assert_eq!(cart.read_rom(0x0000), 0x11);
+    }
+
+    #[test]
+    fn mmm01_menu_is_detected() {
+        let mut rom = mmm01_rom(0x0B);
+        let menu = &mut rom[0x8000..];
+        menu[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        menu[0x14D] = menu[0x134..0x14D]
+            .iter()
+            .fold(0_u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+
+        // the menu is mapped at 0x0000 until a game is chosen
+        let cart = Cartridge::new(&rom, None).unwrap();
+        assert_eq!(cart.read_rom(0x0000), 0x22);
+
+        // a wrong checksum is a stray byte again
+        rom[0x814D] ^= 1;
+        let stray = Cartridge::new(&rom, None).unwrap();
+        assert_eq!(stray.read_rom(0x0000), 0x11);
+    }
+
+    // 1 MiB MBC1 ROM starting every bank with its number,
+    // with the logo at the games in `games`
+    #[allow(clippy::cast_possible_truncation)]
+    fn mbc1_multicart(games: &[usize]) -> Vec<u8> {
+        let mut rom = alloc::vec![0; 0x10_0000];
+        for (bank, data) in rom.chunks_exact_mut(ROM_BANK_SIZE).enumerate() {
+            data[0] = bank as u8;
+        }
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x05;
+        for bank in games {
+            let logo = bank * ROM_BANK_SIZE + LOGO_START;
+            rom[logo..logo + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        rom
+    }
+
+    #[test]
+    fn mbc1_multicart_detection() {
+        for (games, multicart) in [
+            (&[][..], false),
+            (&[0x10], false),
+            (&[0x20], false),
+            (&[0x10, 0x30], true),
+            (&[0x10, 0x20, 0x30], true),
+        ] {
+            let rom = mbc1_multicart(games);
+            assert_eq!(is_mbc1_multicart(&rom), multicart);
+            let cart = Cartridge::new(&rom, None).unwrap();
+            assert_eq!(cart.mbc1_multicart(), multicart);
+        }
+
+        // other sizes are never multicarts
+        let mut rom = mbc1_multicart(&[0x10, 0x20, 0x30]);
+        rom.truncate(0x8_0000);
+        assert!(!is_mbc1_multicart(&rom));
+    }
+
+    #[test]
+    fn mbc1_multicart_override() {
+        let mut cart = Cartridge::new(&mbc1_multicart(&[0x10, 0x20]), None).unwrap();
+        cart.write_rom(0x2000, 0x03);
+        cart.write_rom(0x4000, 0x01);
+        // 4 bit ROM bank
+        assert_eq!(cart.read_rom(0x4000), 0x13);
+
+        cart.set_mbc1_multicart(false);
+        assert!(!cart.mbc1_multicart());
+        assert_eq!(cart.read_rom(0x4000), 0x23);
+
+        cart.set_mbc1_multicart(true);
+        assert_eq!(cart.read_rom(0x4000), 0x13);
+    }
+}
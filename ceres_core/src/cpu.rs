@@ -1279,6 +1279,7 @@ impl Gb {
     #[inline]
     fn ld_b_b(&mut self) {
         self.nop();
+        self.check_mooneye_result();
     }
 
     #[inline]
@@ -0,0 +1,360 @@
+use crate::cartridge::{InitializationError, RAMSize, ROMSize};
+
+const TITLE_START: usize = 0x134;
+const MANUFACTURER_START: usize = 0x13F;
+const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE_START: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+const CART_TYPE: usize = 0x147;
+const OLD_LICENSEE: usize = 0x14B;
+const MASK_ROM_VERSION: usize = 0x14C;
+const HEADER_CHECKSUM: usize = 0x14D;
+const GLOBAL_CHECKSUM: usize = 0x14E;
+const HEADER_END: usize = 0x150;
+
+// an old licensee code of 0x33 means the new licensee code
+// is used instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+/// ``GameBoy`` Color support stated in the header.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CgbFlag {
+    /// Made before the ``GameBoy`` Color.
+    None,
+    /// Enhanced for the ``GameBoy`` Color but still works
+    /// on older models.
+    Compatible,
+    /// Only works on the ``GameBoy`` Color.
+    Only,
+}
+
+/// Information stored in the cartridge header, from 0x100
+/// to 0x14F.
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    title: [u8; 16],
+    title_len: u8,
+    manufacturer_code: Option<[u8; 4]>,
+    cgb_flag: CgbFlag,
+    sgb_flag: bool,
+    new_licensee_code: Option<[u8; 2]>,
+    old_licensee_code: u8,
+    cartridge_type: u8,
+    mask_rom_version: u8,
+    rom_size: usize,
+    ram_size: usize,
+    header_checksum_valid: bool,
+    global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    /// Parses the header of `rom`, the whole ROM is needed
+    /// to validate the global checksum.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `rom` is too small to contain a
+    /// header, the title has non ASCII characters or the
+    /// ROM and RAM sizes are invalid.
+    pub fn new(rom: &[u8]) -> Result<Self, InitializationError> {
+        if rom.len() < HEADER_END {
+            return Err(InitializationError::RomSizeMismatch);
+        }
+
+        let cgb_flag = match rom[CGB_FLAG] {
+            0xC0 => CgbFlag::Only,
+            f if f & 0x80 != 0 => CgbFlag::Compatible,
+            _ => CgbFlag::None,
+        };
+
+        // newer cartridges use the last bytes of the title for
+        // the manufacturer code and the CGB flag
+        let manufacturer_code = rom[MANUFACTURER_START..CGB_FLAG]
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+            .then(|| {
+                let mut code = [0; 4];
+                code.copy_from_slice(&rom[MANUFACTURER_START..CGB_FLAG]);
+                code
+            })
+            .filter(|_| cgb_flag != CgbFlag::None);
+
+        let title_end = match (manufacturer_code, cgb_flag) {
+            (Some(_), _) => MANUFACTURER_START,
+            (None, CgbFlag::None) => NEW_LICENSEE_START,
+            (None, _) => CGB_FLAG,
+        };
+
+        let title_bytes = &rom[TITLE_START..title_end];
+        let title_bytes = title_bytes
+            .iter()
+            .position(|&c| c == 0)
+            .map_or(title_bytes, |len| &title_bytes[..len]);
+
+        if !title_bytes.is_ascii() {
+            return Err(InitializationError::NonAsciiTitleString);
+        }
+
+        let mut title = [0; 16];
+        title[..title_bytes.len()].copy_from_slice(title_bytes);
+
+        let old_licensee_code = rom[OLD_LICENSEE];
+        let new_licensee_code = (old_licensee_code == USE_NEW_LICENSEE)
+            .then(|| [rom[NEW_LICENSEE_START], rom[NEW_LICENSEE_START + 1]]);
+
+        let header_checksum = rom[TITLE_START..HEADER_CHECKSUM]
+            .iter()
+            .fold(0_u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+
+        let global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != GLOBAL_CHECKSUM && i != GLOBAL_CHECKSUM + 1)
+            .fold(0_u16, |x, (_, &b)| x.wrapping_add(u16::from(b)));
+
+        // title is at most 16 bytes long
+        #[allow(clippy::cast_possible_truncation)]
+        let title_len = title_bytes.len() as u8;
+
+        Ok(Self {
+            title,
+            title_len,
+            manufacturer_code,
+            cgb_flag,
+            sgb_flag: rom[SGB_FLAG] == 0x03,
+            new_licensee_code,
+            old_licensee_code,
+            cartridge_type: rom[CART_TYPE],
+            mask_rom_version: rom[MASK_ROM_VERSION],
+            rom_size: ROMSize::new(rom)?.size_bytes(),
+            ram_size: RAMSize::new(rom)?.total_size_in_bytes(),
+            header_checksum_valid: header_checksum == rom[HEADER_CHECKSUM],
+            global_checksum_valid: global_checksum
+                == u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]),
+        })
+    }
+
+    /// Game title in upper case ASCII.
+    #[must_use]
+    pub fn title(&self) -> &str {
+        core::str::from_utf8(&self.title[..self.title_len as usize]).unwrap_or_default()
+    }
+
+    /// Four character manufacturer code, only present in
+    /// some newer cartridges.
+    #[must_use]
+    pub fn manufacturer_code(&self) -> Option<&str> {
+        self.manufacturer_code
+            .as_ref()
+            .and_then(|c| core::str::from_utf8(c).ok())
+    }
+
+    #[must_use]
+    pub fn cgb_flag(&self) -> CgbFlag {
+        self.cgb_flag
+    }
+
+    /// Returns true if the game supports Super ``GameBoy``
+    /// functions.
+    #[must_use]
+    pub fn sgb_flag(&self) -> bool {
+        self.sgb_flag
+    }
+
+    /// Two character publisher code, only used when the old
+    /// licensee code is 0x33.
+    #[must_use]
+    pub fn new_licensee_code(&self) -> Option<[u8; 2]> {
+        self.new_licensee_code
+    }
+
+    #[must_use]
+    pub fn old_licensee_code(&self) -> u8 {
+        self.old_licensee_code
+    }
+
+    #[must_use]
+    pub fn mask_rom_version(&self) -> u8 {
+        self.mask_rom_version
+    }
+
+    /// Raw cartridge type byte at 0x147.
+    #[must_use]
+    pub fn cartridge_type(&self) -> u8 {
+        self.cartridge_type
+    }
+
+    /// Name of the hardware in the cartridge, as listed in
+    /// the Pan Docs.
+    #[must_use]
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+
+    /// ROM size in bytes.
+    #[must_use]
+    pub fn rom_size(&self) -> usize {
+        self.rom_size
+    }
+
+    /// RAM size in bytes, doesn't include the RAM built
+    /// into some MBCs.
+    #[must_use]
+    pub fn ram_size(&self) -> usize {
+        self.ram_size
+    }
+
+    /// Returns true if the header checksum at 0x14D
+    /// matches, the boot ROM locks up otherwise.
+    #[must_use]
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum_valid
+    }
+
+    /// Returns true if the global checksum at 0x14E
+    /// matches, it isn't verified by the hardware.
+    #[must_use]
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum_valid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{CartridgeHeader, CgbFlag, GLOBAL_CHECKSUM, HEADER_CHECKSUM, HEADER_END},
+        crate::cartridge::InitializationError,
+        alloc::vec::Vec,
+    };
+
+    // MBC1 with 8 KiB of RAM, published under the new
+    // licensee code "01", with valid checksums
+    fn build(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+        let mut rom = alloc::vec![0; HEADER_END];
+        rom[0x143] = cgb_flag;
+        // old titles run over the CGB flag
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        rom[0x14B] = 0x33;
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[HEADER_CHECKSUM] = rom[0x134..HEADER_CHECKSUM]
+            .iter()
+            .fold(0_u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+        rom[GLOBAL_CHECKSUM..HEADER_END].fill(0);
+        let global = rom.iter().fold(0_u16, |x, &b| x.wrapping_add(u16::from(b)));
+        rom[GLOBAL_CHECKSUM..HEADER_END].copy_from_slice(&global.to_be_bytes());
+    }
+
+    #[test]
+    fn fields() {
+        let parsed = CartridgeHeader::new(&build(b"TETRIS", 0)).unwrap();
+        assert_eq!(parsed.title(), "TETRIS");
+        assert_eq!(parsed.cgb_flag(), CgbFlag::None);
+        assert_eq!(parsed.manufacturer_code(), None);
+        assert_eq!(parsed.old_licensee_code(), 0x33);
+        assert_eq!(parsed.new_licensee_code(), Some(*b"01"));
+        assert_eq!(parsed.cartridge_type_name(), "MBC1+RAM+BATTERY");
+        assert_eq!(parsed.rom_size(), 0x8000);
+        assert_eq!(parsed.ram_size(), 0x2000);
+        assert!(parsed.header_checksum_valid());
+        assert!(parsed.global_checksum_valid());
+    }
+
+    #[test]
+    fn old_licensee() {
+        // the new code is only used if the old one is 0x33
+        let mut rom = build(b"TETRIS", 0);
+        rom[0x14B] = 0x01;
+        fix_checksums(&mut rom);
+        let parsed = CartridgeHeader::new(&rom).unwrap();
+        assert_eq!(parsed.old_licensee_code(), 0x01);
+        assert_eq!(parsed.new_licensee_code(), None);
+    }
+
+    #[test]
+    fn cgb_flag_shortens_the_title() {
+        // without a CGB flag the title takes 16 bytes
+        let old = CartridgeHeader::new(&build(b"ABCDEFGHIJKLMNOP", 0)).unwrap();
+        assert_eq!(old.cgb_flag(), CgbFlag::None);
+        assert_eq!(old.title(), "ABCDEFGHIJKLMNOP");
+
+        let compatible = CartridgeHeader::new(&build(b"LINK'S AWAKE DX", 0x80)).unwrap();
+        assert_eq!(compatible.cgb_flag(), CgbFlag::Compatible);
+        assert_eq!(compatible.title(), "LINK'S AWAKE DX");
+        assert_eq!(compatible.manufacturer_code(), None);
+
+        // the last 4 bytes are the manufacturer code
+        let only = CartridgeHeader::new(&build(b"POKEMON_SLVAAXE", 0xC0)).unwrap();
+        assert_eq!(only.cgb_flag(), CgbFlag::Only);
+        assert_eq!(only.title(), "POKEMON_SLV");
+        assert_eq!(only.manufacturer_code(), Some("AAXE"));
+    }
+
+    #[test]
+    fn bad_header_checksum() {
+        // a changed title breaks both checksums
+        let mut rom = build(b"TETRIS", 0);
+        rom[0x134] = b'F';
+        let parsed = CartridgeHeader::new(&rom).unwrap();
+        assert!(!parsed.header_checksum_valid());
+        assert!(!parsed.global_checksum_valid());
+    }
+
+    #[test]
+    fn bad_global_checksum() {
+        // the global checksum doesn't cover itself
+        let mut rom = build(b"TETRIS", 0);
+        rom[GLOBAL_CHECKSUM + 1] ^= 1;
+        let parsed = CartridgeHeader::new(&rom).unwrap();
+        assert!(parsed.header_checksum_valid());
+        assert!(!parsed.global_checksum_valid());
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(matches!(
+            CartridgeHeader::new(&[0; 0x14F]),
+            Err(InitializationError::RomSizeMismatch)
+        ));
+        assert!(matches!(
+            CartridgeHeader::new(&build(b"T\xC9TRIS", 0)),
+            Err(InitializationError::NonAsciiTitleString)
+        ));
+    }
+}
@@ -0,0 +1,279 @@
+use {
+    crate::{
+        savestate::{StateError, StateReader, StateWriter},
+        TC_SEC,
+    },
+    alloc::vec::Vec,
+};
+
+const MINUTES_PER_DAY: u16 = 60 * 24;
+
+/// Size of the clock appended to ``HuC3`` save files.
+pub(crate) const HUC3_TRAILER_SIZE: usize = 17;
+
+/// The clock of ``HuC3`` cartridges. The CPU talks to it
+/// through commands written to 0xA000, which read and write
+/// a small register file through an address register.
+#[derive(Default)]
+pub(crate) struct Huc3 {
+    // minute of the day
+    minutes: u16,
+    days: u16,
+    alarm_minutes: u16,
+    alarm_days: u16,
+    alarm_enabled: bool,
+
+    addr: u8,
+    response: u8,
+    flags: u8,
+
+    seconds: u8,
+    cycles: u32,
+    // time the save file was written, to catch up with the
+    // time passed since then
+    saved_at: Option<u64>,
+}
+
+impl Huc3 {
+    pub(crate) fn run(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= TC_SEC {
+            self.cycles -= TC_SEC;
+            self.seconds += 1;
+            if self.seconds == 60 {
+                self.seconds = 0;
+                self.tick_minute();
+            }
+        }
+    }
+
+    fn tick_minute(&mut self) {
+        self.minutes += 1;
+        if self.minutes >= MINUTES_PER_DAY {
+            self.minutes = 0;
+            self.days = self.days.wrapping_add(1);
+        }
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        let seconds = seconds + u64::from(self.seconds);
+        let minutes = seconds / 60 + u64::from(self.minutes);
+        let days = minutes / u64::from(MINUTES_PER_DAY);
+
+        // the remainders fit in their counters
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            self.seconds = (seconds - seconds / 60 * 60) as u8;
+            self.minutes = (minutes - days * u64::from(MINUTES_PER_DAY)) as u16;
+            self.days = self.days.wrapping_add(days as u16);
+        }
+    }
+
+    // mode 0xC reads the response of the last command, mode
+    // 0xD reads if the clock is ready for a command
+    pub(crate) fn read(&self, mode: u8) -> u8 {
+        match mode {
+            0xC if self.flags == 2 => 1,
+            0xC => self.response,
+            _ => 1,
+        }
+    }
+
+    // Commands are in the top nibble and their argument in
+    // the bottom one. Registers 0 to 2 hold the minute of
+    // the day and 3 to 6 the day, one nibble each, 0x58 to
+    // 0x5F the alarm.
+    pub(crate) fn write_command(&mut self, val: u8) {
+        let arg = val & 0xF;
+
+        match val >> 4 {
+            // read and increment
+            1 => {
+                self.response = self.read_register();
+                self.addr = self.addr.wrapping_add(1);
+            }
+            // write, 3 increments
+            cmd @ (2 | 3) => {
+                self.write_register(arg);
+                if cmd == 3 {
+                    self.addr = self.addr.wrapping_add(1);
+                }
+            }
+            4 => self.addr = self.addr & 0xF0 | arg,
+            5 => self.addr = self.addr & 0x0F | arg << 4,
+            6 => self.flags = arg,
+            _ => (),
+        }
+    }
+
+    // nibbles always fit
+    #[allow(clippy::cast_possible_truncation)]
+    fn read_register(&self) -> u8 {
+        let nibble = |val: u16, i: u8| (val >> (i * 4)) as u8 & 0xF;
+
+        match self.addr {
+            0..=2 => nibble(self.minutes, self.addr),
+            3..=6 => nibble(self.days, self.addr - 3),
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, arg: u8) {
+        fn set_nibble(val: &mut u16, i: u8, arg: u8) {
+            let shift = i * 4;
+            *val = *val & !(0xF << shift) | u16::from(arg) << shift;
+        }
+
+        match self.addr {
+            0..=2 => {
+                set_nibble(&mut self.minutes, self.addr, arg);
+                self.seconds = 0;
+                self.cycles = 0;
+            }
+            3..=6 => set_nibble(&mut self.days, self.addr - 3, arg),
+            0x58..=0x5A => set_nibble(&mut self.alarm_minutes, self.addr - 0x58, arg),
+            0x5B..=0x5E => set_nibble(&mut self.alarm_days, self.addr - 0x5B, arg),
+            0x5F => self.alarm_enabled = arg & 1 != 0,
+            _ => (),
+        }
+    }
+
+    pub(crate) fn catch_up(&mut self, unix_time: u64) {
+        if let Some(saved_at) = self.saved_at.take() {
+            self.advance(unix_time.saturating_sub(saved_at));
+        }
+    }
+
+    // a little endian timestamp followed by the minute of
+    // the day, the day, the alarm minute and day and if the
+    // alarm is enabled
+    pub(crate) fn read_trailer(&mut self, trailer: &[u8]) {
+        let u16_at = |i: usize| u16::from_le_bytes([trailer[i], trailer[i + 1]]);
+
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&trailer[..8]);
+        self.saved_at = Some(u64::from_le_bytes(timestamp));
+
+        self.minutes = u16_at(8) % MINUTES_PER_DAY;
+        self.days = u16_at(10);
+        self.alarm_minutes = u16_at(12);
+        self.alarm_days = u16_at(14);
+        self.alarm_enabled = trailer[16] & 1 != 0;
+    }
+
+    pub(crate) fn write_trailer(&self, unix_time: u64) -> Vec<u8> {
+        let mut trailer = Vec::with_capacity(HUC3_TRAILER_SIZE);
+        trailer.extend_from_slice(&unix_time.to_le_bytes());
+        for val in [self.minutes, self.days, self.alarm_minutes, self.alarm_days] {
+            trailer.extend_from_slice(&val.to_le_bytes());
+        }
+        trailer.push(u8::from(self.alarm_enabled));
+        trailer
+    }
+
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.minutes);
+        w.write_u16(self.days);
+        w.write_u16(self.alarm_minutes);
+        w.write_u16(self.alarm_days);
+        w.write_bool(self.alarm_enabled);
+        w.write_u8(self.addr);
+        w.write_u8(self.response);
+        w.write_u8(self.flags);
+        w.write_u8(self.seconds);
+        w.write_u32(self.cycles);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.minutes = r.read_u16()? % MINUTES_PER_DAY;
+        self.days = r.read_u16()?;
+        self.alarm_minutes = r.read_u16()?;
+        self.alarm_days = r.read_u16()?;
+        self.alarm_enabled = r.read_bool()?;
+        self.addr = r.read_u8()?;
+        self.response = r.read_u8()?;
+        self.flags = r.read_u8()?;
+        self.seconds = r.read_u8()?.min(59);
+        self.cycles = r.read_u32()?.min(TC_SEC - 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Huc3, HUC3_TRAILER_SIZE},
+        crate::TC_SEC,
+        alloc::vec::Vec,
+    };
+
+    fn set_addr(chip: &mut Huc3, addr: u8) {
+        chip.write_command(0x40 | addr & 0xF);
+        chip.write_command(0x50 | addr >> 4);
+    }
+
+    // writes the nibbles from `addr` upwards
+    fn write_nibbles(chip: &mut Huc3, addr: u8, nibbles: &[u8]) {
+        set_addr(chip, addr);
+        for nibble in nibbles {
+            chip.write_command(0x30 | nibble);
+        }
+    }
+
+    // the minute of the day and the day
+    fn read_nibbles(chip: &mut Huc3) -> Vec<u8> {
+        set_addr(chip, 0);
+        (0..7)
+            .map(|_| {
+                chip.write_command(0x10);
+                chip.read(0xC)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nibble_write_and_read() {
+        let mut chip = Huc3::default();
+        // minute 0x05B and day 0x0012
+        write_nibbles(&mut chip, 0, &[0xB, 0x5, 0x0, 0x2, 0x1, 0x0, 0x0]);
+        assert_eq!(read_nibbles(&mut chip), [0xB, 0x5, 0x0, 0x2, 0x1, 0x0, 0x0]);
+
+        chip.run(TC_SEC * 60);
+        assert_eq!(read_nibbles(&mut chip), [0xC, 0x5, 0x0, 0x2, 0x1, 0x0, 0x0]);
+
+        // a single write doesn't move the address
+        set_addr(&mut chip, 1);
+        chip.write_command(0x27);
+        chip.write_command(0x26);
+        assert_eq!(read_nibbles(&mut chip), [0xC, 0x6, 0x0, 0x2, 0x1, 0x0, 0x0]);
+
+        // not ready while flagged busy
+        chip.write_command(0x62);
+        assert_eq!(chip.read(0xC), 1);
+        chip.write_command(0x60);
+        assert_eq!(chip.read(0xC), 0);
+    }
+
+    #[test]
+    fn trailer_round_trip() {
+        let mut chip = Huc3::default();
+        // minute 1439, the last of the day, and day 0x0123
+        write_nibbles(&mut chip, 0, &[0xF, 0x9, 0x5, 0x3, 0x2, 0x1, 0x0]);
+        // an enabled alarm at minute 0x0AB of day 0x0004
+        write_nibbles(&mut chip, 0x58, &[0xB, 0xA, 0x0, 0x4, 0x0, 0x0, 0x0, 0x1]);
+
+        let trailer = chip.write_trailer(1000);
+        assert_eq!(trailer.len(), HUC3_TRAILER_SIZE);
+
+        let mut loaded = Huc3::default();
+        loaded.read_trailer(&trailer);
+        assert_eq!(loaded.write_trailer(1000), trailer);
+
+        // a minute later it's the next day
+        loaded.catch_up(1060);
+        assert_eq!(
+            read_nibbles(&mut loaded),
+            [0x0, 0x0, 0x0, 0x4, 0x2, 0x1, 0x0]
+        );
+    }
+}
@@ -1,10 +1,10 @@
 //! A library to make ``GameBoy`` Color emulators.
 //! This library is pretty low level and uses unsafe to
-//! avoid allocations.
+//! avoid allocations, every ``GameBoy`` is allocated once
+//! on the heap.
 
 #![no_std]
 #![feature(core_intrinsics)]
-#![feature(const_maybe_uninit_zeroed)]
 #![warn(
     clippy::pedantic,
     clippy::as_underscore,
@@ -45,28 +45,59 @@
     clippy::verbose_bit_mask
 )]
 
+extern crate alloc;
 #[cfg(feature = "disassembler")]
 extern crate std;
 
 use {
+    alloc::{
+        alloc::{alloc_zeroed, handle_alloc_error},
+        boxed::Box,
+        vec::Vec,
+    },
     apu::{Noise, Square1, Square2, Wave},
-    cartridge::Cartridge,
-    core::{mem::MaybeUninit, time::Duration},
+    core::{alloc::Layout, ptr::addr_of_mut, time::Duration},
     memory::HdmaState,
     ppu::{ColorPalette, Mode, RgbaBuf, OAM_SIZE, VRAM_SIZE_CGB},
 };
 pub use {
-    cartridge::InitializationError,
+    camera::{ImageSource, StillImage, CAMERA_HEIGHT, CAMERA_WIDTH},
+    cartridge::{Cartridge, InitializationError},
+    header::{CartridgeHeader, CgbFlag},
     joypad::Button,
+    link::LinkCable,
+    mapper::Mapper,
     ppu::{PX_HEIGHT, PX_WIDTH},
+    printer::{Printer, Printout, PRINTER_WIDTH},
+    rewind::Rewind,
+    savestate::StateError,
+    serial::SerialDevice,
+    test_rom::TestResult,
 };
 
 mod apu;
+mod bess;
+mod camera;
 mod cartridge;
 mod cpu;
+mod header;
+mod huc3;
 mod joypad;
+mod link;
+mod mapper;
+mod mbc;
+mod mbc6;
+mod mbc7;
 mod memory;
+mod mmm01;
 mod ppu;
+mod printer;
+mod rewind;
+mod rtc;
+mod savestate;
+mod serial;
+mod tama5;
+mod test_rom;
 mod timing;
 
 const DMG_BOOTROM: &[u8] = include_bytes!("../bootroms/bin/dmg_boot.bin");
@@ -83,7 +114,7 @@ const TC_SEC: u32 = 0x0040_0000;
 const IF_VBLANK_B: u8 = 1;
 const IF_LCD_B: u8 = 2;
 const IF_TIMER_B: u8 = 4;
-//const IF_SERIAL_B: u8 = 8;
+const IF_SERIAL_B: u8 = 8;
 const IF_P1_B: u8 = 0x10;
 
 const KEY1_SPEED_B: u8 = 0x80;
@@ -96,13 +127,35 @@ const WRAM_SIZE_CGB: usize = WRAM_SIZE * 4;
 pub type Sample = i16;
 
 /// ``GameBoy`` model to emulate.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Model {
     Dmg,
     Mgb,
     Cgb,
 }
 
+impl Model {
+    /// Chooses the model the game was made for. Games that
+    /// support the ``GameBoy`` Color run on it, the rest
+    /// run on the original ``GameBoy``, as Super
+    /// ``GameBoy`` enhancements aren't emulated.
+    #[must_use]
+    pub fn from_header(header: &CartridgeHeader) -> Self {
+        match header.cgb_flag() {
+            CgbFlag::Only | CgbFlag::Compatible => Model::Cgb,
+            CgbFlag::None => Model::Dmg,
+        }
+    }
+
+    const fn boot_rom(self) -> &'static [u8] {
+        match self {
+            Model::Dmg => DMG_BOOTROM,
+            Model::Mgb => MGB_BOOTROM,
+            Model::Cgb => CGB_BOOTROM,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 enum CompatMode {
     Dmg,
@@ -110,18 +163,19 @@ enum CompatMode {
     Cgb,
 }
 
-pub static mut GAME_BOY: Gb = unsafe { MaybeUninit::zeroed().assume_init() };
-
 /// The ``GameBoy`` struct is the main struct in the
-/// library. The `run` method never returns and calls a PPU
-/// "graphical" callback every frame and an APU "audio"
-/// callback every sample. These callbacks are passed to
-/// the `new` function, which returns a `GameBoy` struct.
+/// library. The `run_frame` method emulates a whole frame
+/// and calls an APU "audio" callback every sample. This
+/// callback is passed to the `new` function, which returns
+/// a boxed `GameBoy` struct. Every instance is independent
+/// so many of them can run at the same time.
 pub struct Gb {
     // general
     model: Model,
     compat_mode: CompatMode,
     running_frame: bool,
+    // t-cycles at normal speed since power on
+    elapsed_cycles: u64,
 
     // double speed
     double_speed: bool,
@@ -146,6 +200,15 @@ pub struct Gb {
     // serial
     sb: u8,
     sc: u8,
+    serial_bits: u8,
+    serial_in: u8,
+    serial_callback: Option<fn(u8)>,
+    serial_device: Option<Box<dyn SerialDevice>>,
+
+    // test ROMs
+    test_passed: bool,
+    test_failed: bool,
+    serial_tail: [u8; 6],
 
     // joypad
     p1_btn: u8,
@@ -235,20 +298,16 @@ pub struct Gb {
 }
 
 impl Gb {
-    /// # Errors
-    ///
-    /// Will return `Err` if the ROM header contains some
-    /// unsupported MBC value. This can happen if the ROM is
-    /// corrupt, has not been initialized or we simply don't
-    /// support its MBC yet.
+    /// Creates a new ``GameBoy`` emulating `model` with
+    /// `cart` inserted.
+    #[must_use]
     pub fn new(
         model: Model,
+        cart: Cartridge,
         apu_frame_callback: fn(Sample, Sample),
         sample_rate: u32,
-    ) -> Result<&'static mut Self, InitializationError> {
-        let mut gb = unsafe { &mut GAME_BOY };
-
-        gb.cart.init()?;
+    ) -> Box<Self> {
+        let mut gb = Self::alloc_zeroed(cart);
 
         // custom initilization
         gb.model = model;
@@ -258,11 +317,7 @@ impl Gb {
             Model::Cgb => CompatMode::Cgb,
         };
 
-        gb.boot_rom = Some(match model {
-            Model::Dmg => DMG_BOOTROM,
-            Model::Mgb => MGB_BOOTROM,
-            Model::Cgb => CGB_BOOTROM,
-        });
+        gb.boot_rom = Some(model.boot_rom());
 
         gb.svbk_true = 1;
         gb.ppu_cycles = Mode::HBlank.cycles(0);
@@ -280,7 +335,27 @@ impl Gb {
         gb.apu_ch4 = Noise::default();
         gb.hdma_state = HdmaState::default();
 
-        Ok(gb)
+        gb
+    }
+
+    // The struct is too big to be built on the stack, so we
+    // allocate it directly on the heap. Every field is valid
+    // when zeroed but the ones owning memory, which have to be
+    // written in place.
+    fn alloc_zeroed(cart: Cartridge) -> Box<Self> {
+        let layout = Layout::new::<Self>();
+
+        unsafe {
+            // the layout guarantees alignment
+            #[allow(clippy::cast_ptr_alignment)]
+            let ptr = alloc_zeroed(layout).cast::<Self>();
+            if ptr.is_null() {
+                handle_alloc_error(layout);
+            }
+            addr_of_mut!((*ptr).cart).write(cart);
+            addr_of_mut!((*ptr).serial_device).write(None);
+            Box::from_raw(ptr)
+        }
     }
 
     fn set_apu_frame_callback(&mut self, apu_frame_callback: fn(Sample, Sample)) {
@@ -302,6 +377,19 @@ impl Gb {
         }
     }
 
+    /// Sets a callback called with every byte sent through
+    /// the serial port, useful to capture the output of
+    /// test ROMs.
+    pub fn set_serial_callback(&mut self, serial_callback: fn(u8)) {
+        self.serial_callback = Some(serial_callback);
+    }
+
+    /// Returns the emulated model.
+    #[must_use]
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
     #[must_use]
     pub fn pixel_data(&self) -> &[u8] {
         self.rgba_buf.pixel_data()
@@ -310,31 +398,74 @@ impl Gb {
     /// Returns true if cartridge has battery, false
     /// otherwise
     #[must_use]
-    pub fn cartridge_has_battery() -> bool {
-        unsafe { GAME_BOY.cart.has_battery() }
+    pub fn cartridge_has_battery(&self) -> bool {
+        self.cart.has_battery()
+    }
+
+    /// Tilts the accelerometer of MBC7 cartridges, `x` and
+    /// `y` are the acceleration in thousandths of g,
+    /// positive when tilted right and down.
+    pub fn set_accelerometer(&mut self, x: i32, y: i32) {
+        self.cart.set_accelerometer(x, y);
     }
 
-    /// Returns reference to static RAM slice.
+    /// Sets where the Pocket Camera takes its pictures
+    /// from, other cartridges ignore it.
+    pub fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        self.cart.set_camera_source(source);
+    }
+
+    /// Returns true while the motor of a rumble cartridge
+    /// is on. Games pulse it to control its strength, so it
+    /// should be polled every frame.
     #[must_use]
-    pub fn cartridge_ram() -> &'static [u8] {
-        unsafe { GAME_BOY.cart.ram() }
+    pub fn cartridge_rumble(&self) -> bool {
+        self.cart.rumble()
     }
 
-    /// Returns mutable reference to static RAM slice.
-    ///
-    /// Modifying the RAM contents while the Gb is running
-    /// could lead to undesirable results.
+    /// Returns true if the cartridge RAM or clock were
+    /// written since the cartridge was loaded or the last
+    /// call to `mark_cartridge_ram_saved`.
     #[must_use]
-    pub fn cartridge_ram_mut() -> &'static mut [u8] {
-        unsafe { GAME_BOY.cart.mut_ram() }
+    pub fn cartridge_ram_dirty(&self) -> bool {
+        self.cart.save_dirty()
     }
 
-    /// Returns mutable reference to static ROM slice.
+    /// Should be called after writing the cartridge RAM to
+    /// the save file.
+    pub fn mark_cartridge_ram_saved(&mut self) {
+        self.cart.clear_save_dirty();
+    }
+
+    /// Advances the cartridge clock by the time passed
+    /// since its save file was written, `unix_time` is
+    /// the current time in seconds. Should be called
+    /// once after loading a save.
+    pub fn catch_up_cartridge_rtc(&mut self, unix_time: u64) {
+        self.cart.catch_up_rtc(unix_time);
+    }
+
+    /// Returns the cartridge clock, if any, in the format
+    /// appended to save files, including `unix_time` to
+    /// catch up when loaded. MBC3 clocks use the 48 byte
+    /// format of VBA and BGB.
+    #[must_use]
+    pub fn cartridge_rtc_trailer(&self, unix_time: u64) -> Option<Vec<u8>> {
+        self.cart.rtc_trailer(unix_time)
+    }
+
+    /// Returns reference to cartridge RAM slice.
+    #[must_use]
+    pub fn cartridge_ram(&self) -> &[u8] {
+        self.cart.ram()
+    }
+
+    /// Returns mutable reference to cartridge RAM slice.
     ///
-    /// Modifying the ROM contents while the Gb is running
+    /// Modifying the RAM contents while the Gb is running
     /// could lead to undesirable results.
     #[must_use]
-    pub fn cartridge_rom_mut() -> &'static mut [u8] {
-        unsafe { GAME_BOY.cart.mut_rom() }
+    pub fn cartridge_ram_mut(&mut self) -> &mut [u8] {
+        self.cart.mut_ram()
     }
 }
@@ -0,0 +1,141 @@
+use {
+    crate::{serial::SerialDevice, Gb},
+    alloc::{boxed::Box, rc::Rc},
+    core::cell::RefCell,
+};
+
+#[derive(Default)]
+struct Port {
+    // SB while waiting for a transfer clocked by the other
+    // side
+    waiting: Option<u8>,
+    received: Option<u8>,
+}
+
+type Wire = Rc<RefCell<[Port; 2]>>;
+
+struct LinkPort {
+    wire: Wire,
+    side: usize,
+}
+
+impl SerialDevice for LinkPort {
+    fn exchange(&mut self, sent: u8) -> u8 {
+        let mut ports = self.wire.borrow_mut();
+        let other = &mut ports[1 - self.side];
+
+        match other.waiting.take() {
+            Some(sb) => {
+                other.received = Some(sent);
+                sb
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll_external(&mut self, _: u8) -> Option<u8> {
+        self.wire.borrow_mut()[self.side].received.take()
+    }
+}
+
+/// A link cable connecting two ``GameBoy``s in the same
+/// process. Both are run by `run_frame` in lockstep, so
+/// serial transfers see the current state of the other
+/// side.
+pub struct LinkCable {
+    wire: Wire,
+}
+
+impl LinkCable {
+    /// Connects `a` and `b`, replacing their serial
+    /// devices. The same pair has to be passed to
+    /// `run_frame`.
+    #[must_use]
+    pub fn new(a: &mut Gb, b: &mut Gb) -> Self {
+        let wire = Wire::default();
+
+        for (side, gb) in [a, b].into_iter().enumerate() {
+            gb.connect_serial(Box::new(LinkPort {
+                wire: Rc::clone(&wire),
+                side,
+            }));
+        }
+
+        Self { wire }
+    }
+
+    /// Runs a frame of both ``GameBoy``s, interleaving
+    /// their instructions to keep them within a few
+    /// cycles of each other.
+    pub fn run_frame(&mut self, a: &mut Gb, b: &mut Gb) {
+        let mut gbs = [a, b];
+        let start = gbs.each_ref().map(|gb| gb.elapsed_cycles);
+
+        for gb in &mut gbs {
+            gb.running_frame = true;
+        }
+
+        while gbs.iter().any(|gb| gb.running_frame) {
+            let behind = |i: usize| gbs[i].elapsed_cycles - start[i];
+            let side = match (gbs[0].running_frame, gbs[1].running_frame) {
+                (true, true) => usize::from(behind(1) < behind(0)),
+                (running_a, _) => usize::from(!running_a),
+            };
+
+            let gb = &mut *gbs[side];
+            gb.run_cpu();
+
+            self.wire.borrow_mut()[side].waiting = gb.waiting_serial_byte();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::LinkCable,
+        crate::{Cartridge, Gb, Model, IF_SERIAL_B},
+        alloc::boxed::Box,
+    };
+
+    fn gb(code: &[u8]) -> Box<Gb> {
+        let mut rom = alloc::vec![0; 0x8000];
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+        let cart = Cartridge::new(&rom, None).unwrap();
+        Gb::new(Model::Cgb, cart, |_, _| (), 48000)
+    }
+
+    #[test]
+    fn exchange_bytes() {
+        // waits a bit for the other side, then sends 0x17
+        // with the internal clock
+        let mut a = gb(&[
+            0x06, 0x00, // ld b, 0
+            0x05, // dec b
+            0x20, 0xFD, // jr nz, -3
+            0x3E, 0x17, // ld a, 0x17
+            0xE0, 0x01, // ldh (SB), a
+            0x3E, 0x81, // ld a, 0x81
+            0xE0, 0x02, // ldh (SC), a
+            0x18, 0xFE, // jr -2
+        ]);
+        // sends 0x42 with the external clock
+        let mut b = gb(&[
+            0x3E, 0x42, // ld a, 0x42
+            0xE0, 0x01, // ldh (SB), a
+            0x3E, 0x80, // ld a, 0x80
+            0xE0, 0x02, // ldh (SC), a
+            0x18, 0xFE, // jr -2
+        ]);
+
+        let mut cable = LinkCable::new(&mut a, &mut b);
+        cable.run_frame(&mut a, &mut b);
+
+        assert_eq!(a.sb, 0x42);
+        assert_eq!(b.sb, 0x17);
+        for gb in [&a, &b] {
+            assert_eq!(gb.sc & 0x80, 0);
+            assert_ne!(gb.ifr & IF_SERIAL_B, 0);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use {crate::savestate::StateError, alloc::vec::Vec};
+
+/// The chip of a cartridge that maps its ROM, RAM and
+/// anything else it has at 0x0000-0x7FFF and
+/// 0xA000-0xBFFF. Every built-in MBC implements it.
+/// Homebrew mappers and flash carts can implement it and
+/// be loaded with `Cartridge::with_mapper`.
+pub trait Mapper {
+    /// Reads from 0x0000-0x7FFF.
+    fn read_rom(&self, addr: u16) -> u8;
+
+    /// Writes to 0x0000-0x7FFF, usually to the banking
+    /// registers.
+    fn write_rom(&mut self, addr: u16, val: u8);
+
+    /// Reads from 0xA000-0xBFFF.
+    fn read_ram(&self, addr: u16) -> u8;
+
+    /// Writes to 0xA000-0xBFFF.
+    fn write_ram(&mut self, addr: u16, val: u8);
+
+    /// Runs clocks and other timed hardware for `cycles`
+    /// normal speed t-cycles, 4194304 a second.
+    fn run(&mut self, _cycles: u32) {}
+
+    /// The data written to the save file, empty if the
+    /// cartridge has no battery.
+    fn save_data(&self) -> &[u8] {
+        &[]
+    }
+
+    /// The data written to the save file, to load it.
+    fn save_data_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /// Returns true if the save data changed since the
+    /// cartridge was loaded or the last call to
+    /// `clear_save_dirty`.
+    fn save_dirty(&self) -> bool {
+        false
+    }
+
+    /// Called after writing the save data to the save file.
+    fn clear_save_dirty(&mut self) {}
+
+    /// Size in bytes of the state kept in save states
+    /// besides the save data, it can't change once loaded.
+    fn state_size(&self) -> usize {
+        0
+    }
+
+    /// Writes the state of the mapper to `buf`, which is
+    /// `state_size` bytes long.
+    fn save_state(&self, _buf: &mut [u8]) {}
+
+    /// Restores the state written by `save_state`.
+    ///
+    /// # Errors
+    ///
+    /// Should return `Err` if `buf` isn't a valid state.
+    fn load_state(&mut self, _buf: &[u8]) -> Result<(), StateError> {
+        Ok(())
+    }
+
+    /// Advances the clock, if any, by the time passed since
+    /// it was saved. `unix_time` is in seconds since the
+    /// Unix epoch.
+    fn catch_up(&mut self, _unix_time: u64) {}
+
+    /// The clock appended to the save file, if any, saved
+    /// at `unix_time`.
+    fn clock_data(&self, _unix_time: u64) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores a clock written by `clock_data`, ignored if
+    /// it has the wrong size.
+    fn set_clock_data(&mut self, _data: &[u8]) {}
+
+    /// Returns true while the rumble motor is on.
+    fn rumble(&self) -> bool {
+        false
+    }
+}
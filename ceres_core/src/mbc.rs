@@ -0,0 +1,1312 @@
+//! The built-in MBCs, each one a `Mapper` that owns the ROM
+//! and RAM of its cartridge.
+
+use {
+    crate::{
+        camera::{self, ImageSource},
+        huc3::{self, HUC3_TRAILER_SIZE},
+        mapper::Mapper,
+        mbc6::{self, FLASH_SIZE},
+        mbc7, mmm01,
+        rtc::{Rtc, RTC_TRAILER_SIZE, RTC_TRAILER_SIZE_32},
+        savestate::{StateError, StateReader, StateWriter},
+        tama5::{self, TAMA5_TRAILER_SIZE},
+    },
+    alloc::{boxed::Box, vec::Vec},
+};
+
+pub(crate) const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+// reads of the infrared port when no light is seen
+const IR_NO_LIGHT: u8 = 0xC0;
+
+/// The ROM and RAM of a cartridge and the offsets of the
+/// banks mapped.
+pub(crate) struct Banks {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    rom_offsets: (usize, usize),
+    ram_offset: usize,
+    // RAM or clock written since the last save
+    dirty: bool,
+}
+
+impl Banks {
+    pub(crate) fn new(rom: Box<[u8]>, ram: Box<[u8]>) -> Self {
+        Self {
+            rom,
+            ram,
+            rom_offsets: (0x0000, ROM_BANK_SIZE),
+            ram_offset: 0,
+            dirty: false,
+        }
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank_addr = match addr {
+            0x0000..=0x3FFF => {
+                let (rom_lower, _) = self.rom_offsets;
+                rom_lower | (addr as usize & 0x3FFF)
+            }
+            0x4000..=0x7FFF => {
+                let (_, rom_upper) = self.rom_offsets;
+                rom_upper | (addr as usize & 0x3FFF)
+            }
+            _ => 0,
+        };
+
+        // sizes are powers of two, out of bounds banks are
+        // mirrored
+        self.rom[bank_addr & (self.rom.len() - 1)]
+    }
+
+    fn set_rom_bank(&mut self, bank: usize) {
+        self.rom_offsets = (0x0000, ROM_BANK_SIZE * bank);
+    }
+
+    fn set_ram_bank(&mut self, bank: usize) {
+        self.ram_offset = RAM_BANK_SIZE * bank;
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_offset | (addr as usize & 0x1FFF)) & (self.ram.len() - 1)
+    }
+
+    fn read_ram(&self, ram_enabled: bool, addr: u16) -> u8 {
+        if ram_enabled && !self.ram.is_empty() {
+            self.ram[self.ram_addr(addr)]
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram_enabled: bool, addr: u16, val: u8) {
+        if ram_enabled && !self.ram.is_empty() {
+            let addr = self.ram_addr(addr);
+            self.ram[addr] = val;
+            self.dirty = true;
+        }
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        self.dirty = true;
+        &mut self.ram
+    }
+
+    // The RAM is saved as save data, offsets always fit in
+    // 32 bits, biggest ROM is 8 MiB.
+    #[allow(clippy::cast_possible_truncation)]
+    fn write_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.rom_offsets.0 as u32);
+        w.write_u32(self.rom_offsets.1 as u32);
+        w.write_u32(self.ram_offset as u32);
+    }
+
+    // offsets are masked when used, no need to validate them
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let rom_lower = r.read_u32()? as usize;
+        let rom_upper = r.read_u32()? as usize;
+        self.rom_offsets = (rom_lower, rom_upper);
+        self.ram_offset = r.read_u32()? as usize;
+        Ok(())
+    }
+}
+
+// size of the state written by `write_state`
+fn state_size(write_state: impl FnOnce(&mut StateWriter)) -> usize {
+    let mut w = StateWriter::new(&mut []);
+    write_state(&mut w);
+    w.pos()
+}
+
+/// The hooks of the chips of the built-in MBCs, which
+/// custom mappers don't have.
+pub(crate) trait MapperExt: Mapper {
+    /// Sets the tilt read by the accelerometer, if any.
+    fn set_accelerometer(&mut self, _x: i32, _y: i32) {}
+
+    /// Sets where the camera, if any, takes its pictures
+    /// from.
+    fn set_camera_source(&mut self, _source: Box<dyn ImageSource>) {}
+
+    /// Returns true if the ROM bank is wired for a
+    /// multicart, only MBC1 has such variants.
+    fn multicart(&self) -> bool {
+        false
+    }
+
+    /// Overrides the multicart wiring detected when
+    /// loading.
+    fn set_multicart(&mut self, _multicart: bool) {}
+
+    /// Calls `f` with the writes to 0x0000-0x7FFF that
+    /// restore the banking registers after
+    /// `reset_registers`, used to export BESS save states.
+    fn for_each_register_write(&self, _f: &mut dyn FnMut(u16, u8)) {}
+
+    /// Resets registers that can't be written anymore, like
+    /// those of MMM01 once a game is mapped, before
+    /// replaying register writes.
+    fn reset_registers(&mut self) {}
+}
+
+/// A mapper given to `Cartridge::with_mapper`, without any
+/// of the hooks of `MapperExt`.
+pub(crate) struct Custom(Box<dyn Mapper>);
+
+impl Custom {
+    pub(crate) fn new(mapper: Box<dyn Mapper>) -> Self {
+        Self(mapper)
+    }
+}
+
+impl Mapper for Custom {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.0.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        self.0.write_rom(addr, val);
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.0.read_ram(addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.0.write_ram(addr, val);
+    }
+
+    fn run(&mut self, cycles: u32) {
+        self.0.run(cycles);
+    }
+
+    fn save_data(&self) -> &[u8] {
+        self.0.save_data()
+    }
+
+    fn save_data_mut(&mut self) -> &mut [u8] {
+        self.0.save_data_mut()
+    }
+
+    fn save_dirty(&self) -> bool {
+        self.0.save_dirty()
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.0.clear_save_dirty();
+    }
+
+    fn state_size(&self) -> usize {
+        self.0.state_size()
+    }
+
+    fn save_state(&self, buf: &mut [u8]) {
+        self.0.save_state(buf);
+    }
+
+    fn load_state(&mut self, buf: &[u8]) -> Result<(), StateError> {
+        self.0.load_state(buf)
+    }
+
+    fn catch_up(&mut self, unix_time: u64) {
+        self.0.catch_up(unix_time);
+    }
+
+    fn clock_data(&self, unix_time: u64) -> Option<Vec<u8>> {
+        self.0.clock_data(unix_time)
+    }
+
+    fn set_clock_data(&mut self, data: &[u8]) {
+        self.0.set_clock_data(data);
+    }
+
+    fn rumble(&self) -> bool {
+        self.0.rumble()
+    }
+}
+
+impl MapperExt for Custom {}
+
+// The `Mapper` methods every MBC shares, the save data is
+// the RAM of the `Banks` at `$banks` and the state is the
+// one of the inherent `write_state` and `read_state`.
+macro_rules! save_methods {
+    ($($banks:ident).+) => {
+        fn save_data(&self) -> &[u8] {
+            &self.$($banks).+.ram
+        }
+
+        fn save_data_mut(&mut self) -> &mut [u8] {
+            self.$($banks).+.ram_mut()
+        }
+
+        fn save_dirty(&self) -> bool {
+            self.$($banks).+.dirty
+        }
+
+        fn clear_save_dirty(&mut self) {
+            self.$($banks).+.dirty = false;
+        }
+
+        fn state_size(&self) -> usize {
+            state_size(|w| self.write_state(w))
+        }
+
+        fn save_state(&self, buf: &mut [u8]) {
+            self.write_state(&mut StateWriter::new(buf));
+        }
+
+        fn load_state(&mut self, buf: &[u8]) -> Result<(), StateError> {
+            self.read_state(&mut StateReader::new(buf))
+        }
+    };
+}
+
+fn ram_enable(enabled: bool) -> u8 {
+    if enabled {
+        0x0A
+    } else {
+        0
+    }
+}
+
+/// ROM only cartridges, with RAM that is always enabled if
+/// they have any.
+pub(crate) struct NoMbc {
+    banks: Banks,
+}
+
+impl NoMbc {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self { banks }
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)
+    }
+}
+
+impl Mapper for NoMbc {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.banks.read_ram(true, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks.write_ram(true, addr, val);
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for NoMbc {}
+
+/// MBC1, up to 2 MiB of ROM and 32 KiB of RAM.
+pub(crate) struct Mbc1 {
+    banks: Banks,
+    ram_enabled: bool,
+    rom_bank_lo: u8,
+    rom_bank_hi: u8,
+    bank_mode: bool,
+    // multicarts wire the ROM bank with 4 bits instead of 5
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub(crate) fn new(banks: Banks, multicart: bool) -> Self {
+        Self {
+            banks,
+            ram_enabled: false,
+            rom_bank_lo: 1,
+            rom_bank_hi: 0,
+            bank_mode: false,
+            multicart,
+        }
+    }
+
+    fn update_offsets(&mut self) {
+        let upper_bits = if self.multicart {
+            self.rom_bank_hi << 4
+        } else {
+            self.rom_bank_hi << 5
+        };
+        let lower_bits = if self.multicart {
+            self.rom_bank_lo & 0xF
+        } else {
+            self.rom_bank_lo
+        };
+
+        let lower_bank = if self.bank_mode {
+            upper_bits as usize
+        } else {
+            0
+        };
+        let upper_bank = (upper_bits | lower_bits) as usize;
+        self.banks.rom_offsets = (ROM_BANK_SIZE * lower_bank, ROM_BANK_SIZE * upper_bank);
+
+        let ram_bank = if self.bank_mode {
+            self.rom_bank_hi as usize
+        } else {
+            0
+        };
+        self.banks.set_ram_bank(ram_bank);
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.rom_bank_lo);
+        w.write_u8(self.rom_bank_hi);
+        w.write_bool(self.bank_mode);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.rom_bank_lo = r.read_u8()?;
+        self.rom_bank_hi = r.read_u8()?;
+        self.bank_mode = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = (val & 0xF) == 0xA,
+            0x2000..=0x3FFF => {
+                let val = val & 0x1F;
+                self.rom_bank_lo = if val == 0 { 1 } else { val };
+                self.update_offsets();
+            }
+            0x4000..=0x5FFF => {
+                self.rom_bank_hi = val & 3;
+                self.update_offsets();
+            }
+            0x6000..=0x7FFF => {
+                self.bank_mode = val & 1 != 0;
+                self.update_offsets();
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.banks.read_ram(self.ram_enabled, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks.write_ram(self.ram_enabled, addr, val);
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for Mbc1 {
+    fn multicart(&self) -> bool {
+        self.multicart
+    }
+
+    fn set_multicart(&mut self, multicart: bool) {
+        self.multicart = multicart;
+        self.update_offsets();
+    }
+
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, ram_enable(self.ram_enabled));
+        f(0x2000, self.rom_bank_lo);
+        f(0x4000, self.rom_bank_hi);
+        f(0x6000, u8::from(self.bank_mode));
+    }
+}
+
+/// MBC2, up to 256 KiB of ROM and 512 half bytes of RAM
+/// built in, mirrored through 0xA000-0xBFFF.
+pub(crate) struct Mbc2 {
+    banks: Banks,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self {
+            banks,
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.rom_bank);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.rom_bank = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    // bit 8 of the address selects the register
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        if addr <= 0x3FFF {
+            if (addr >> 8) & 1 == 0 {
+                self.ram_enabled = (val & 0xF) == 0xA;
+            } else {
+                let val = val & 0xF;
+                self.rom_bank = if val == 0 { 1 } else { val };
+                self.banks.set_rom_bank(self.rom_bank as usize);
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        (self.banks.read_ram(self.ram_enabled, addr) & 0xF) | 0xF0
+    }
+
+    // only the low nibble is stored
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks.write_ram(self.ram_enabled, addr, val & 0xF);
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for Mbc2 {
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, ram_enable(self.ram_enabled));
+        f(0x0100, self.rom_bank);
+    }
+}
+
+/// MBC3 and MBC30, the latter with 8 RAM banks, both
+/// optionally with a real time clock.
+pub(crate) struct Mbc3 {
+    banks: Banks,
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 0x08 to 0x0C select clock registers
+    ram_bank: u8,
+    mbc30: bool,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub(crate) fn new(banks: Banks, mbc30: bool, has_rtc: bool) -> Self {
+        Self {
+            banks,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            mbc30,
+            rtc: has_rtc.then(Rtc::default),
+        }
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        if let Some(rtc) = &self.rtc {
+            rtc.write_state(w);
+        }
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.rom_bank = r.read_u8()?;
+        self.ram_bank = r.read_u8()?;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.read_state(r)?;
+        }
+        Ok(())
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = (val & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = if val == 0 { 1 } else { val & 0x7F };
+                self.banks.set_rom_bank(self.rom_bank as usize);
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank = val & 0xF;
+                if self.mbc30 {
+                    self.banks.set_ram_bank((self.ram_bank & 0x7) as usize);
+                } else {
+                    self.banks.set_ram_bank((self.ram_bank & 0x3) as usize);
+                }
+            }
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match (self.ram_bank, &self.rtc) {
+            (0x00..=0x03, _) => self.banks.read_ram(self.ram_enabled, addr),
+            (0x04..=0x07, _) => self.banks.read_ram(self.ram_enabled && self.mbc30, addr),
+            (0x08..=0x0C, Some(rtc)) if self.ram_enabled => rtc.read(self.ram_bank),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        match (self.ram_bank, &mut self.rtc) {
+            (0x00..=0x03, _) => self.banks.write_ram(self.ram_enabled, addr, val),
+            (0x04..=0x07, _) => {
+                self.banks
+                    .write_ram(self.ram_enabled && self.mbc30, addr, val);
+            }
+            (0x08..=0x0C, Some(rtc)) if self.ram_enabled => {
+                rtc.write(self.ram_bank, val);
+                self.banks.dirty = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.run(cycles);
+        }
+    }
+
+    save_methods!(banks);
+
+    fn catch_up(&mut self, unix_time: u64) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.catch_up(unix_time);
+        }
+    }
+
+    fn clock_data(&self, unix_time: u64) -> Option<Vec<u8>> {
+        self.rtc
+            .as_ref()
+            .map(|rtc| rtc.write_trailer(unix_time).to_vec())
+    }
+
+    fn set_clock_data(&mut self, data: &[u8]) {
+        if let (Some(rtc), RTC_TRAILER_SIZE | RTC_TRAILER_SIZE_32) = (&mut self.rtc, data.len()) {
+            rtc.read_trailer(data);
+            self.banks.dirty = true;
+        }
+    }
+}
+
+impl MapperExt for Mbc3 {
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, ram_enable(self.ram_enabled));
+        f(0x2000, self.rom_bank);
+        f(0x4000, self.ram_bank);
+    }
+}
+
+/// MBC5, up to 8 MiB of ROM and 128 KiB of RAM. Bit 3 of
+/// the RAM bank drives the motor of rumble cartridges.
+pub(crate) struct Mbc5 {
+    banks: Banks,
+    ram_enabled: bool,
+    rom_bank_lo: u8,
+    rom_bank_hi: u8,
+    ram_bank: u8,
+    // bit mask of rom bank, anded with the rom bank selected
+    // gets the actual rom bank depending on the ROM size
+    rom_bank_mask: usize,
+    has_rumble: bool,
+}
+
+impl Mbc5 {
+    pub(crate) fn new(banks: Banks, rom_bank_mask: usize, has_rumble: bool) -> Self {
+        Self {
+            banks,
+            ram_enabled: false,
+            rom_bank_lo: 1,
+            rom_bank_hi: 0,
+            ram_bank: 0,
+            rom_bank_mask,
+            has_rumble,
+        }
+    }
+
+    fn update_rom_offsets(&mut self) {
+        let lower_bits = self.rom_bank_lo as usize;
+        let upper_bits = (self.rom_bank_hi as usize) << 8;
+        self.banks
+            .set_rom_bank((upper_bits | lower_bits) & self.rom_bank_mask);
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.rom_bank_lo);
+        w.write_u8(self.rom_bank_hi);
+        w.write_u8(self.ram_bank);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.rom_bank_lo = r.read_u8()?;
+        self.rom_bank_hi = r.read_u8()?;
+        self.ram_bank = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
+            0x2000..=0x2FFF => {
+                self.rom_bank_lo = val;
+                self.update_rom_offsets();
+            }
+            0x3000..=0x3FFF => {
+                self.rom_bank_hi = val & 1;
+                self.update_rom_offsets();
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank = val & 0xF;
+                let bank = if self.has_rumble {
+                    self.ram_bank & 0x7
+                } else {
+                    self.ram_bank
+                };
+                self.banks.set_ram_bank(bank as usize);
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.banks.read_ram(self.ram_enabled, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks.write_ram(self.ram_enabled, addr, val);
+    }
+
+    save_methods!(banks);
+
+    fn rumble(&self) -> bool {
+        self.has_rumble && self.ram_bank & 8 != 0
+    }
+}
+
+impl MapperExt for Mbc5 {
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, ram_enable(self.ram_enabled));
+        f(0x2000, self.rom_bank_lo);
+        f(0x3000, self.rom_bank_hi);
+        f(0x4000, self.ram_bank);
+    }
+}
+
+/// MBC6, with its flash chip saved after the RAM.
+pub(crate) struct Mbc6 {
+    banks: Banks,
+    ram_enabled: bool,
+    chip: mbc6::Mbc6,
+}
+
+impl Mbc6 {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self {
+            banks,
+            ram_enabled: false,
+            chip: mbc6::Mbc6::default(),
+        }
+    }
+
+    fn sram_len(&self) -> usize {
+        self.banks.ram.len() - FLASH_SIZE
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        self.chip.write_state(w);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.chip.read_state(r)
+    }
+}
+
+impl Mapper for Mbc6 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        if addr >= 0x4000 {
+            let flash = &self.banks.ram[self.sram_len()..];
+            self.chip.read_rom(addr, &self.banks.rom, flash)
+        } else {
+            self.banks.read_rom(addr)
+        }
+    }
+
+    // writes to 0x4000 go to the flash chip if mapped there
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x03FF => self.ram_enabled = val & 0xF == 0xA,
+            0x0400..=0x3FFF => self.chip.write_register(addr, val),
+            _ => {
+                let flash_start = self.sram_len();
+                self.banks.dirty |=
+                    self.chip
+                        .write_flash(addr, val, &mut self.banks.ram[flash_start..]);
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        let sram_len = self.sram_len();
+        if self.ram_enabled && sram_len != 0 {
+            self.banks.ram[self.chip.ram_addr(addr) & (sram_len - 1)]
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        let sram_len = self.sram_len();
+        if self.ram_enabled && sram_len != 0 {
+            self.banks.ram[self.chip.ram_addr(addr) & (sram_len - 1)] = val;
+            self.banks.dirty = true;
+        }
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for Mbc6 {
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, ram_enable(self.ram_enabled));
+        self.chip.for_each_write(f);
+    }
+}
+
+/// MBC7, with its EEPROM saved as RAM and an accelerometer.
+pub(crate) struct Mbc7 {
+    banks: Banks,
+    ram_enabled: bool,
+    rom_bank: u8,
+    chip: mbc7::Mbc7,
+}
+
+impl Mbc7 {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self {
+            banks,
+            ram_enabled: false,
+            rom_bank: 1,
+            chip: mbc7::Mbc7::default(),
+        }
+    }
+
+    // the EEPROM and accelerometer are only mapped at
+    // 0xA000-0xAFFF
+    fn mapped(&self, addr: u16) -> bool {
+        self.ram_enabled && self.chip.ram_enabled() && addr < 0xB000
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.rom_bank);
+        self.chip.write_state(w);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.rom_bank = r.read_u8()?;
+        self.chip.read_state(r)
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
+            0x2000..=0x3FFF => {
+                self.rom_bank = val;
+                self.banks.set_rom_bank(self.rom_bank as usize);
+            }
+            0x4000..=0x5FFF => self.chip.write_ram_enable(val),
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.mapped(addr) {
+            self.chip.read(addr)
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.mapped(addr) {
+            self.banks.dirty |= self.chip.write(addr, val, &mut self.banks.ram);
+        }
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for Mbc7 {
+    fn set_accelerometer(&mut self, x: i32, y: i32) {
+        self.chip.set_accelerometer(x, y);
+    }
+
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, ram_enable(self.ram_enabled));
+        f(0x2000, self.rom_bank);
+        f(0x4000, if self.chip.ram_enabled() { 0x40 } else { 0 });
+    }
+}
+
+/// MMM01 multicarts, the menu and the games they map.
+pub(crate) struct Mmm01 {
+    banks: Banks,
+    ram_enabled: bool,
+    chip: mmm01::Mmm01,
+}
+
+impl Mmm01 {
+    pub(crate) fn new(banks: Banks) -> Self {
+        let mut mapper = Self {
+            banks,
+            ram_enabled: false,
+            chip: mmm01::Mmm01::default(),
+        };
+        mapper.update_offsets();
+        mapper
+    }
+
+    fn update_offsets(&mut self) {
+        self.banks.rom_offsets = self.chip.rom_offsets(self.banks.rom.len());
+        self.banks.set_ram_bank(self.chip.ram_bank());
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        self.chip.write_state(w);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.chip.read_state(r)
+    }
+}
+
+impl Mapper for Mmm01 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        if addr <= 0x1FFF {
+            self.ram_enabled = val & 0xF == 0xA;
+        }
+        self.chip.write(addr, val);
+        self.update_offsets();
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.banks.read_ram(self.ram_enabled, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks.write_ram(self.ram_enabled, addr, val);
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for Mmm01 {
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        self.chip.for_each_write(ram_enable(self.ram_enabled), f);
+    }
+
+    // the registers can't be written once mapped
+    fn reset_registers(&mut self) {
+        self.chip = mmm01::Mmm01::default();
+    }
+}
+
+/// The Pocket Camera. RAM bank 0x10 maps the camera
+/// registers.
+pub(crate) struct PocketCamera {
+    banks: Banks,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    camera: camera::Camera,
+}
+
+impl PocketCamera {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self {
+            banks,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            camera: camera::Camera::new(),
+        }
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        self.camera.write_state(w);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.ram_enabled = r.read_bool()?;
+        self.rom_bank = r.read_u8()?;
+        self.ram_bank = r.read_u8()?;
+        self.camera.read_state(r)
+    }
+}
+
+impl Mapper for PocketCamera {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
+            0x2000..=0x3FFF => {
+                self.rom_bank = val & 0x3F;
+                self.banks.set_rom_bank(self.rom_bank as usize);
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank = val & 0x1F;
+                self.banks.set_ram_bank((self.ram_bank & 0xF) as usize);
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram_bank & 0x10 != 0 {
+            self.camera.read(addr)
+        } else if self.camera.busy() {
+            // the RAM can't be read while taking a picture
+            0
+        } else {
+            self.banks.read_ram(self.ram_enabled, addr)
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_bank & 0x10 != 0 {
+            self.camera.write(addr, val);
+        } else {
+            self.banks.write_ram(self.ram_enabled, addr, val);
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        self.banks.dirty |= self.camera.run(cycles, &mut self.banks.ram);
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for PocketCamera {
+    fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        self.camera.set_source(source);
+    }
+
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, ram_enable(self.ram_enabled));
+        f(0x2000, self.rom_bank);
+        f(0x4000, self.ram_bank);
+    }
+}
+
+/// TAMA5, its banks and clock are selected through
+/// registers at 0xA000.
+pub(crate) struct Tama5 {
+    banks: Banks,
+    chip: tama5::Tama5,
+}
+
+impl Tama5 {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self {
+            banks,
+            chip: tama5::Tama5::default(),
+        }
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        self.chip.write_state(w);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.chip.read_state(r)
+    }
+}
+
+impl Mapper for Tama5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.chip.read(addr, &self.banks.ram)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        self.banks.dirty |= self.chip.write(addr, val, &mut self.banks.ram);
+        self.banks.set_rom_bank(self.chip.rom_bank());
+    }
+
+    fn run(&mut self, cycles: u32) {
+        self.chip.run(cycles);
+    }
+
+    save_methods!(banks);
+
+    fn catch_up(&mut self, unix_time: u64) {
+        self.chip.catch_up(unix_time);
+    }
+
+    fn clock_data(&self, unix_time: u64) -> Option<Vec<u8>> {
+        Some(self.chip.write_trailer(unix_time))
+    }
+
+    fn set_clock_data(&mut self, data: &[u8]) {
+        if data.len() == TAMA5_TRAILER_SIZE {
+            self.chip.read_trailer(data);
+            self.banks.dirty = true;
+        }
+    }
+}
+
+impl MapperExt for Tama5 {}
+
+/// ``HuC1``, maps RAM or its infrared port at 0xA000. The
+/// RAM is always enabled.
+pub(crate) struct Huc1 {
+    banks: Banks,
+    // selects what is mapped at 0xA000
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl Huc1 {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self {
+            banks,
+            mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.banks.write_state(w);
+        w.write_u8(self.mode);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.banks.read_state(r)?;
+        self.mode = r.read_u8()?;
+        self.rom_bank = r.read_u8()?;
+        self.ram_bank = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Mapper for Huc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.banks.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = val & 0xF,
+            0x2000..=0x3FFF => {
+                let val = val & 0x7F;
+                self.rom_bank = if val == 0 { 1 } else { val };
+                self.banks.set_rom_bank(self.rom_bank as usize);
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank = val & 0xF;
+                self.banks.set_ram_bank(self.ram_bank as usize);
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.mode == 0x0E {
+            IR_NO_LIGHT
+        } else {
+            self.banks.read_ram(true, addr)
+        }
+    }
+
+    // writes in infrared mode drive its LED
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.mode != 0x0E {
+            self.banks.write_ram(true, addr, val);
+        }
+    }
+
+    save_methods!(banks);
+}
+
+impl MapperExt for Huc1 {
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        f(0x0000, self.mode);
+        f(0x2000, self.rom_bank);
+        f(0x4000, self.ram_bank);
+    }
+}
+
+/// ``HuC3``, banked like ``HuC1`` with a clock also mapped
+/// at 0xA000.
+pub(crate) struct Huc3 {
+    huc1: Huc1,
+    chip: huc3::Huc3,
+}
+
+impl Huc3 {
+    pub(crate) fn new(banks: Banks) -> Self {
+        Self {
+            huc1: Huc1::new(banks),
+            chip: huc3::Huc3::default(),
+        }
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        self.huc1.write_state(w);
+        self.chip.write_state(w);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.huc1.read_state(r)?;
+        self.chip.read_state(r)
+    }
+}
+
+impl Mapper for Huc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.huc1.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        self.huc1.write_rom(addr, val);
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match self.huc1.mode {
+            0x0 | 0xA => self.huc1.banks.read_ram(true, addr),
+            0xC | 0xD => self.chip.read(self.huc1.mode),
+            0xE => IR_NO_LIGHT,
+            _ => 1,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        match self.huc1.mode {
+            0xA => self.huc1.banks.write_ram(true, addr, val),
+            0xB => {
+                self.chip.write_command(val);
+                self.huc1.banks.dirty = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        self.chip.run(cycles);
+    }
+
+    save_methods!(huc1.banks);
+
+    fn catch_up(&mut self, unix_time: u64) {
+        self.chip.catch_up(unix_time);
+    }
+
+    fn clock_data(&self, unix_time: u64) -> Option<Vec<u8>> {
+        Some(self.chip.write_trailer(unix_time))
+    }
+
+    fn set_clock_data(&mut self, data: &[u8]) {
+        if data.len() == HUC3_TRAILER_SIZE {
+            self.chip.read_trailer(data);
+            self.huc1.banks.dirty = true;
+        }
+    }
+}
+
+impl MapperExt for Huc3 {
+    fn for_each_register_write(&self, f: &mut dyn FnMut(u16, u8)) {
+        self.huc1.for_each_register_write(f);
+    }
+}
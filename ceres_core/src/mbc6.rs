@@ -0,0 +1,328 @@
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+/// Size of the flash chip, saved after the RAM.
+pub(crate) const FLASH_SIZE: usize = 0x10_0000;
+
+const ROM_BANK_SIZE: usize = 0x2000;
+const RAM_BANK_SIZE: usize = 0x1000;
+const FLASH_SECTOR_SIZE: usize = 0x2_0000;
+// programs write up to a page at once
+const FLASH_PAGE_SIZE: usize = 0x80;
+
+// Macronix MX29F008
+const FLASH_MANUFACTURER_ID: u8 = 0xC2;
+const FLASH_DEVICE_ID: u8 = 0x81;
+
+const SELECT_FLASH: u8 = 8;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum FlashState {
+    #[default]
+    Read,
+    // reads return the chip ID
+    Id,
+    // 0xAA written to 0x5555
+    Unlock1,
+    // 0x55 written to 0x2AAA
+    Unlock2,
+    // 0x80, the erase commands unlock again
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    // after 0xA0, writes program the page of the first one
+    Program {
+        page: Option<usize>,
+        len: usize,
+    },
+}
+
+/// The banking registers of MBC6 cartridges and their flash
+/// chip. 0x4000 and 0x6000 map two 8 KiB windows of ROM or
+/// flash, 0xA000 and 0xB000 two 4 KiB windows of RAM.
+#[derive(Default)]
+pub(crate) struct Mbc6 {
+    rom_banks: [u8; 2],
+    flash_selected: [bool; 2],
+    ram_banks: [u8; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash: FlashState,
+}
+
+impl Mbc6 {
+    pub(crate) fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0400..=0x07FF => self.ram_banks[0] = val & 7,
+            0x0800..=0x0BFF => self.ram_banks[1] = val & 7,
+            0x0C00..=0x0FFF => self.flash_enabled = val & 1 != 0,
+            0x1000 => self.flash_write_enabled = val & 1 != 0,
+            0x2000..=0x27FF => self.rom_banks[0] = val & 0x7F,
+            0x2800..=0x2FFF => self.flash_selected[0] = val & SELECT_FLASH != 0,
+            0x3000..=0x37FF => self.rom_banks[1] = val & 0x7F,
+            0x3800..=0x3FFF => self.flash_selected[1] = val & SELECT_FLASH != 0,
+            _ => (),
+        }
+    }
+
+    // the window of `addr`, 0x4000 or 0x6000, and the address
+    // in the ROM or the flash chip
+    fn window(&self, addr: u16) -> (bool, usize) {
+        let window = usize::from(addr >= 0x6000);
+        let offset = usize::from(self.rom_banks[window]) * ROM_BANK_SIZE;
+        (
+            self.flash_selected[window],
+            offset | usize::from(addr) & (ROM_BANK_SIZE - 1),
+        )
+    }
+
+    pub(crate) fn read_rom(&self, addr: u16, rom: &[u8], flash: &[u8]) -> u8 {
+        match self.window(addr) {
+            (false, rom_addr) => rom[rom_addr & (rom.len() - 1)],
+            (true, flash_addr) if self.flash == FlashState::Id => match flash_addr & 0xFF {
+                0 => FLASH_MANUFACTURER_ID,
+                1 => FLASH_DEVICE_ID,
+                _ => 0,
+            },
+            (true, flash_addr) => flash[flash_addr & (flash.len() - 1)],
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn ram_addr(&self, addr: u16) -> usize {
+        let window = usize::from(addr >= 0xB000);
+        (usize::from(self.ram_banks[window]) * RAM_BANK_SIZE)
+            | (usize::from(addr) & (RAM_BANK_SIZE - 1))
+    }
+
+    // returns true if the flash was written
+    pub(crate) fn write_flash(&mut self, addr: u16, val: u8, flash: &mut [u8]) -> bool {
+        match self.window(addr) {
+            (true, flash_addr) if self.flash_enabled => {
+                self.flash_command(flash_addr & (flash.len() - 1), val, flash)
+            }
+            _ => false,
+        }
+    }
+
+    // The usual JEDEC command sequences, every command
+    // starts writing 0xAA to 0x5555 and 0x55 to 0x2AAA.
+    // Erases and programs finish immediately and need the
+    // write enable at 0x1000.
+    fn flash_command(&mut self, addr: usize, val: u8, flash: &mut [u8]) -> bool {
+        let cmd_addr = addr & 0x7FFF;
+
+        if let FlashState::Program { page, len } = self.flash {
+            let this_page = addr & !(FLASH_PAGE_SIZE - 1);
+            if page.unwrap_or(this_page) == this_page && len < FLASH_PAGE_SIZE {
+                self.flash = FlashState::Program {
+                    page: Some(this_page),
+                    len: len + 1,
+                };
+                return self.program(addr, val, flash);
+            }
+            self.flash = FlashState::Read;
+        }
+
+        if val == 0xF0 {
+            self.flash = FlashState::Read;
+            return false;
+        }
+
+        let mut written = false;
+
+        self.flash = match (self.flash, cmd_addr, val) {
+            (FlashState::Read | FlashState::Id, 0x5555, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0x90) => FlashState::Id,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::Erase,
+            (FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program { page: None, len: 0 },
+            (FlashState::Erase, 0x5555, 0xAA) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x2AAA, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, _, 0x30) if self.flash_write_enabled => {
+                let sector = addr & !(FLASH_SECTOR_SIZE - 1);
+                flash[sector..sector + FLASH_SECTOR_SIZE].fill(0xFF);
+                written = true;
+                FlashState::Read
+            }
+            (FlashState::EraseUnlock2, 0x5555, 0x10) if self.flash_write_enabled => {
+                flash.fill(0xFF);
+                written = true;
+                FlashState::Read
+            }
+            _ => FlashState::Read,
+        };
+
+        written
+    }
+
+    // programming only clears bits, erasing sets them
+    fn program(&mut self, addr: usize, val: u8, flash: &mut [u8]) -> bool {
+        if !self.flash_write_enabled {
+            return false;
+        }
+
+        flash[addr] &= val;
+        true
+    }
+
+    // calls `f` with the register writes that rebuild the
+    // banking state
+    pub(crate) fn for_each_write(&self, mut f: impl FnMut(u16, u8)) {
+        let select = |flash: bool| if flash { SELECT_FLASH } else { 0 };
+
+        f(0x0400, self.ram_banks[0]);
+        f(0x0800, self.ram_banks[1]);
+        f(0x0C00, u8::from(self.flash_enabled));
+        f(0x1000, u8::from(self.flash_write_enabled));
+        f(0x2000, self.rom_banks[0]);
+        f(0x2800, select(self.flash_selected[0]));
+        f(0x3000, self.rom_banks[1]);
+        f(0x3800, select(self.flash_selected[1]));
+    }
+
+    // pages and their length always fit
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        for window in 0..2 {
+            w.write_u8(self.rom_banks[window]);
+            w.write_bool(self.flash_selected[window]);
+            w.write_u8(self.ram_banks[window]);
+        }
+        w.write_bool(self.flash_enabled);
+        w.write_bool(self.flash_write_enabled);
+
+        let (page, len) = match self.flash {
+            FlashState::Program { page, len } => (page, len),
+            _ => (None, 0),
+        };
+        w.write_u8(match self.flash {
+            FlashState::Read => 0,
+            FlashState::Id => 1,
+            FlashState::Unlock1 => 2,
+            FlashState::Unlock2 => 3,
+            FlashState::Erase => 4,
+            FlashState::EraseUnlock1 => 5,
+            FlashState::EraseUnlock2 => 6,
+            FlashState::Program { .. } => 7,
+        });
+        w.write_bool(page.is_some());
+        w.write_u32(page.unwrap_or(0) as u32);
+        w.write_u8(len as u8);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for window in 0..2 {
+            self.rom_banks[window] = r.read_u8()? & 0x7F;
+            self.flash_selected[window] = r.read_bool()?;
+            self.ram_banks[window] = r.read_u8()? & 7;
+        }
+        self.flash_enabled = r.read_bool()?;
+        self.flash_write_enabled = r.read_bool()?;
+
+        let state = r.read_u8()?;
+        let has_page = r.read_bool()?;
+        let page = r.read_u32()? as usize & (FLASH_SIZE - 1) & !(FLASH_PAGE_SIZE - 1);
+        let len = usize::from(r.read_u8()?).min(FLASH_PAGE_SIZE);
+        self.flash = match state {
+            0 => FlashState::Read,
+            1 => FlashState::Id,
+            2 => FlashState::Unlock1,
+            3 => FlashState::Unlock2,
+            4 => FlashState::Erase,
+            5 => FlashState::EraseUnlock1,
+            6 => FlashState::EraseUnlock2,
+            7 => FlashState::Program {
+                page: has_page.then_some(page),
+                len,
+            },
+            _ => return Err(StateError::InvalidValue),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Mbc6, FLASH_DEVICE_ID, FLASH_MANUFACTURER_ID, FLASH_SECTOR_SIZE, FLASH_SIZE},
+        alloc::vec::Vec,
+    };
+
+    // flash bank 2 at 0x4000 and bank 1 at 0x6000, so 0x5555
+    // and 0x6AAA are the command addresses
+    fn mbc6() -> (Mbc6, Vec<u8>) {
+        let mut chip = Mbc6::default();
+        chip.write_register(0x0C00, 1);
+        chip.write_register(0x2000, 2);
+        chip.write_register(0x2800, 8);
+        chip.write_register(0x3000, 1);
+        chip.write_register(0x3800, 8);
+        (chip, alloc::vec![0xFF; FLASH_SIZE])
+    }
+
+    fn command(chip: &mut Mbc6, flash: &mut [u8], cmd: u8) {
+        assert!(!chip.write_flash(0x5555, 0xAA, flash));
+        assert!(!chip.write_flash(0x6AAA, 0x55, flash));
+        assert!(!chip.write_flash(0x5555, cmd, flash));
+    }
+
+    #[test]
+    fn chip_id() {
+        let (mut chip, mut flash) = mbc6();
+        command(&mut chip, &mut flash, 0x90);
+        assert_eq!(chip.read_rom(0x4000, &[], &flash), FLASH_MANUFACTURER_ID);
+        assert_eq!(chip.read_rom(0x4001, &[], &flash), FLASH_DEVICE_ID);
+
+        chip.write_flash(0x4000, 0xF0, &mut flash);
+        assert_eq!(chip.read_rom(0x4000, &[], &flash), 0xFF);
+    }
+
+    #[test]
+    fn program_needs_write_enable() {
+        let (mut chip, mut flash) = mbc6();
+        command(&mut chip, &mut flash, 0xA0);
+        assert!(!chip.write_flash(0x4010, 0x12, &mut flash));
+        assert_eq!(flash[0x4010], 0xFF);
+
+        chip.write_register(0x1000, 1);
+        command(&mut chip, &mut flash, 0xA0);
+        assert!(chip.write_flash(0x4010, 0x12, &mut flash));
+        assert!(chip.write_flash(0x4011, 0x34, &mut flash));
+        assert_eq!(chip.read_rom(0x4010, &[], &flash), 0x12);
+        assert_eq!(chip.read_rom(0x4011, &[], &flash), 0x34);
+
+        // a write outside the page ends the program
+        assert!(!chip.write_flash(0x4100, 0x56, &mut flash));
+        assert_eq!(flash[0x4100], 0xFF);
+
+        // programming only clears bits
+        command(&mut chip, &mut flash, 0xA0);
+        assert!(chip.write_flash(0x4010, 0x21, &mut flash));
+        assert_eq!(flash[0x4010], 0x12 & 0x21);
+    }
+
+    fn erase(chip: &mut Mbc6, flash: &mut [u8], addr: u16, cmd: u8) -> bool {
+        command(chip, flash, 0x80);
+        assert!(!chip.write_flash(0x5555, 0xAA, flash));
+        assert!(!chip.write_flash(0x6AAA, 0x55, flash));
+        chip.write_flash(addr, cmd, flash)
+    }
+
+    #[test]
+    fn erase_needs_write_enable() {
+        let (mut chip, mut flash) = mbc6();
+        flash.fill(0);
+
+        // the sector of flash bank 1, 0x2000-0x3FFF
+        assert!(!erase(&mut chip, &mut flash, 0x6000, 0x30));
+        assert!(flash.iter().all(|&b| b == 0));
+
+        chip.write_register(0x1000, 1);
+        assert!(erase(&mut chip, &mut flash, 0x6000, 0x30));
+        assert!(flash[..FLASH_SECTOR_SIZE].iter().all(|&b| b == 0xFF));
+        assert!(flash[FLASH_SECTOR_SIZE..].iter().all(|&b| b == 0));
+
+        assert!(erase(&mut chip, &mut flash, 0x5555, 0x10));
+        assert!(flash.iter().all(|&b| b == 0xFF));
+    }
+}
@@ -0,0 +1,404 @@
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+/// Size of the 93LC56 EEPROM, 128 words of 16 bits.
+pub(crate) const EEPROM_SIZE: usize = 0x100;
+
+// the accelerometer reads this value when level and moves
+// about 0x70 per g
+const ACCEL_CENTER: u16 = 0x81D0;
+const ACCEL_PER_G: i32 = 0x70;
+const ACCEL_ERASED: u16 = 0x8000;
+
+const EEPROM_CS_B: u8 = 0x80;
+const EEPROM_CLK_B: u8 = 0x40;
+const EEPROM_DI_B: u8 = 2;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum EepromState {
+    // waiting for the start bit
+    #[default]
+    Idle,
+    // opcode and address, 10 bits
+    Command,
+    // shifting out the word in `shift`
+    Read,
+    // shifting in a word, written to `addr` or every word
+    Write {
+        all: bool,
+    },
+    // until CS goes low
+    Done,
+}
+
+/// The sensor and EEPROM of MBC7 cartridges, mapped at
+/// 0xA000 when both RAM enables are set.
+#[derive(Default)]
+pub(crate) struct Mbc7 {
+    // second RAM enable, written at 0x4000
+    ram_enabled: bool,
+
+    // x and y in thousandths of g
+    accel: (i32, i32),
+    latched: (u16, u16),
+    erased: bool,
+
+    cs: bool,
+    clk: bool,
+    data_out: bool,
+    state: EepromState,
+    shift: u16,
+    bits: u8,
+    addr: u8,
+    write_enabled: bool,
+}
+
+impl Mbc7 {
+    pub(crate) fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    pub(crate) fn write_ram_enable(&mut self, val: u8) {
+        self.ram_enabled = val == 0x40;
+    }
+
+    pub(crate) fn set_accelerometer(&mut self, x: i32, y: i32) {
+        self.accel = (x, y);
+    }
+
+    pub(crate) fn read(&self, addr: u16) -> u8 {
+        let [x_lo, x_hi] = self.latched.0.to_le_bytes();
+        let [y_lo, y_hi] = self.latched.1.to_le_bytes();
+
+        match addr & 0xF0F0 {
+            0xA020 => x_lo,
+            0xA030 => x_hi,
+            0xA040 => y_lo,
+            0xA050 => y_hi,
+            0xA060 => 0,
+            0xA080 => u8::from(self.cs) << 7 | u8::from(self.clk) << 6 | u8::from(self.data_out),
+            _ => 0xFF,
+        }
+    }
+
+    // returns true if the EEPROM was written
+    pub(crate) fn write(&mut self, addr: u16, val: u8, eeprom: &mut [u8]) -> bool {
+        match addr & 0xF0F0 {
+            0xA000 if val == 0x55 => {
+                self.erased = true;
+                self.latched = (ACCEL_ERASED, ACCEL_ERASED);
+                false
+            }
+            0xA010 if val == 0xAA && self.erased => {
+                self.erased = false;
+                self.latched = (accel_value(self.accel.0), accel_value(self.accel.1));
+                false
+            }
+            0xA080 => self.write_eeprom_pins(val, eeprom),
+            _ => false,
+        }
+    }
+
+    fn write_eeprom_pins(&mut self, val: u8, eeprom: &mut [u8]) -> bool {
+        let cs = val & EEPROM_CS_B != 0;
+        let clk = val & EEPROM_CLK_B != 0;
+        let rising = cs && clk && !self.clk;
+
+        self.cs = cs;
+        self.clk = clk;
+
+        if !cs {
+            self.state = EepromState::Idle;
+            self.data_out = true;
+            return false;
+        }
+
+        if rising {
+            self.clock_in(val & EEPROM_DI_B != 0, eeprom)
+        } else {
+            false
+        }
+    }
+
+    // Commands start with a 1 bit followed by a 2 bit opcode
+    // and an 8 bit address, the top address bit is unused.
+    // Reads shift out a dummy 0 followed by the word, writes
+    // shift in the word after the address.
+    fn clock_in(&mut self, di: bool, eeprom: &mut [u8]) -> bool {
+        match self.state {
+            EepromState::Idle => {
+                if di {
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            EepromState::Command => {
+                self.shift = self.shift << 1 | u16::from(di);
+                self.bits += 1;
+                if self.bits == 10 {
+                    return self.run_command(eeprom);
+                }
+            }
+            EepromState::Read => {
+                self.data_out = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits += 1;
+                // reads continue with the next word
+                if self.bits == 16 {
+                    self.addr = (self.addr + 1) & 0x7F;
+                    self.shift = read_word(eeprom, self.addr);
+                    self.bits = 0;
+                }
+            }
+            EepromState::Write { all } => {
+                self.shift = self.shift << 1 | u16::from(di);
+                self.bits += 1;
+                if self.bits == 16 {
+                    self.state = EepromState::Done;
+                    if self.write_enabled {
+                        if all {
+                            for addr in 0..0x80 {
+                                write_word(eeprom, addr, self.shift);
+                            }
+                        } else {
+                            write_word(eeprom, self.addr, self.shift);
+                        }
+                        return true;
+                    }
+                }
+            }
+            EepromState::Done => (),
+        }
+
+        false
+    }
+
+    fn run_command(&mut self, eeprom: &mut [u8]) -> bool {
+        let [addr, opcode] = self.shift.to_le_bytes();
+        self.addr = addr & 0x7F;
+        self.shift = 0;
+        self.bits = 0;
+        self.state = EepromState::Done;
+
+        match opcode & 3 {
+            // read
+            0b10 => {
+                self.state = EepromState::Read;
+                self.shift = read_word(eeprom, self.addr);
+                self.data_out = false;
+            }
+            // write
+            0b01 => self.state = EepromState::Write { all: false },
+            // erase
+            0b11 if self.write_enabled => {
+                write_word(eeprom, self.addr, 0xFFFF);
+                return true;
+            }
+            0b00 => match addr >> 6 {
+                0b00 => self.write_enabled = false,
+                0b01 => self.state = EepromState::Write { all: true },
+                0b10 if self.write_enabled => {
+                    eeprom.fill(0xFF);
+                    return true;
+                }
+                0b11 => self.write_enabled = true,
+                _ => (),
+            },
+            _ => (),
+        }
+
+        false
+    }
+
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.ram_enabled);
+        w.write_u16(self.latched.0);
+        w.write_u16(self.latched.1);
+        w.write_bool(self.erased);
+        w.write_bool(self.cs);
+        w.write_bool(self.clk);
+        w.write_bool(self.data_out);
+        w.write_u8(match self.state {
+            EepromState::Idle => 0,
+            EepromState::Command => 1,
+            EepromState::Read => 2,
+            EepromState::Write { all: false } => 3,
+            EepromState::Write { all: true } => 4,
+            EepromState::Done => 5,
+        });
+        w.write_u16(self.shift);
+        w.write_u8(self.bits);
+        w.write_u8(self.addr);
+        w.write_bool(self.write_enabled);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enabled = r.read_bool()?;
+        self.latched = (r.read_u16()?, r.read_u16()?);
+        self.erased = r.read_bool()?;
+        self.cs = r.read_bool()?;
+        self.clk = r.read_bool()?;
+        self.data_out = r.read_bool()?;
+        self.state = match r.read_u8()? {
+            0 => EepromState::Idle,
+            1 => EepromState::Command,
+            2 => EepromState::Read,
+            3 => EepromState::Write { all: false },
+            4 => EepromState::Write { all: true },
+            5 => EepromState::Done,
+            _ => return Err(StateError::InvalidValue),
+        };
+        self.shift = r.read_u16()?;
+        self.bits = r.read_u8()?;
+        // commands run at 10 bits and words are 16 bits
+        let max_bits = if self.state == EepromState::Command {
+            10
+        } else {
+            16
+        };
+        if self.bits >= max_bits {
+            return Err(StateError::InvalidValue);
+        }
+        self.addr = r.read_u8()? & 0x7F;
+        self.write_enabled = r.read_bool()?;
+        Ok(())
+    }
+}
+
+// words are stored little endian, as other emulators do
+fn read_word(eeprom: &[u8], addr: u8) -> u16 {
+    let i = usize::from(addr) * 2;
+    u16::from_le_bytes([eeprom[i], eeprom[i + 1]])
+}
+
+fn write_word(eeprom: &mut [u8], addr: u8, word: u16) {
+    let i = usize::from(addr) * 2;
+    eeprom[i..i + 2].copy_from_slice(&word.to_le_bytes());
+}
+
+// thousandths of g to sensor value
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn accel_value(milli_g: i32) -> u16 {
+    let offset = milli_g.clamp(-4000, 4000) * ACCEL_PER_G / 1000;
+    (i32::from(ACCEL_CENTER) + offset) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Mbc7, EEPROM_CLK_B, EEPROM_CS_B, EEPROM_DI_B, EEPROM_SIZE},
+        crate::savestate::{StateReader, StateWriter},
+    };
+
+    // shifts in the low `len` bits of `bits`, the highest
+    // first, returns true if the EEPROM was written
+    fn send(chip: &mut Mbc7, eeprom: &mut [u8], bits: u16, len: u8) -> bool {
+        let mut written = false;
+        for bit in (0..len).rev() {
+            let di = if bits >> bit & 1 == 0 { 0 } else { EEPROM_DI_B };
+            chip.write(0xA080, EEPROM_CS_B | di, eeprom);
+            written |= chip.write(0xA080, EEPROM_CS_B | EEPROM_CLK_B | di, eeprom);
+        }
+        written
+    }
+
+    // a start bit followed by the opcode and address
+    fn command(chip: &mut Mbc7, eeprom: &mut [u8], opcode: u16, addr: u16) -> bool {
+        chip.write(0xA080, 0, eeprom);
+        send(chip, eeprom, 1 << 10 | opcode << 8 | addr, 11)
+    }
+
+    fn read_word(chip: &mut Mbc7, eeprom: &mut [u8], addr: u16) -> u16 {
+        command(chip, eeprom, 0b10, addr);
+        // a dummy 0 comes first
+        assert_eq!(chip.read(0xA080) & 1, 0);
+        (0..16).fold(0, |word, _| {
+            send(chip, eeprom, 0, 1);
+            word << 1 | u16::from(chip.read(0xA080) & 1)
+        })
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut chip = Mbc7::default();
+        let mut eeprom = [0xFF; EEPROM_SIZE];
+
+        // writes are ignored until enabled
+        command(&mut chip, &mut eeprom, 0b01, 5);
+        assert!(!send(&mut chip, &mut eeprom, 0xBEEF, 16));
+        assert_eq!(read_word(&mut chip, &mut eeprom, 5), 0xFFFF);
+
+        // EWEN
+        command(&mut chip, &mut eeprom, 0b00, 0xC0);
+        command(&mut chip, &mut eeprom, 0b01, 5);
+        assert!(send(&mut chip, &mut eeprom, 0xBEEF, 16));
+        assert_eq!(eeprom[10..12], [0xEF, 0xBE]);
+        assert_eq!(read_word(&mut chip, &mut eeprom, 5), 0xBEEF);
+
+        // EWDS
+        command(&mut chip, &mut eeprom, 0b00, 0x00);
+        command(&mut chip, &mut eeprom, 0b01, 5);
+        assert!(!send(&mut chip, &mut eeprom, 0x1234, 16));
+        assert_eq!(read_word(&mut chip, &mut eeprom, 5), 0xBEEF);
+    }
+
+    fn latched(chip: &Mbc7) -> (u16, u16) {
+        let byte = |addr| u16::from(chip.read(addr));
+        (
+            byte(0xA030) << 8 | byte(0xA020),
+            byte(0xA050) << 8 | byte(0xA040),
+        )
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut chip = Mbc7::default();
+        let mut eeprom = [0xFF; EEPROM_SIZE];
+
+        chip.set_accelerometer(1000, -500);
+        // latching needs an erase first
+        chip.write(0xA010, 0xAA, &mut eeprom);
+        assert_eq!(latched(&chip), (0, 0));
+
+        chip.write(0xA000, 0x55, &mut eeprom);
+        assert_eq!(latched(&chip), (0x8000, 0x8000));
+        chip.write(0xA010, 0xAA, &mut eeprom);
+        assert_eq!(latched(&chip), (0x81D0 + 0x70, 0x81D0 - 0x38));
+
+        // kept until latched again
+        chip.set_accelerometer(0, 0);
+        assert_eq!(latched(&chip), (0x81D0 + 0x70, 0x81D0 - 0x38));
+        chip.write(0xA000, 0x55, &mut eeprom);
+        chip.write(0xA010, 0xAA, &mut eeprom);
+        assert_eq!(latched(&chip), (0x81D0, 0x81D0));
+    }
+
+    #[test]
+    fn invalid_bit_count_is_rejected() {
+        let mut chip = Mbc7::default();
+        let mut eeprom = [0xFF; EEPROM_SIZE];
+
+        // the start bit and 9 bits of a command
+        send(&mut chip, &mut eeprom, 1 << 9 | 0b10 << 7, 10);
+
+        let mut state = [0; 32];
+        let mut w = StateWriter::new(&mut state);
+        chip.write_state(&mut w);
+        // the state, the shift register, the bit count, the
+        // address and the write enable end the state
+        let bits = w.pos() - 3;
+        let eeprom_state = bits - 3;
+        assert_eq!(state[bits], 9);
+        assert!(chip.read_state(&mut StateReader::new(&state)).is_ok());
+
+        state[bits] = 10;
+        assert!(chip.read_state(&mut StateReader::new(&state)).is_err());
+
+        // reads shift out 16 bits
+        state[eeprom_state] = 2;
+        state[bits] = 15;
+        assert!(chip.read_state(&mut StateReader::new(&state)).is_ok());
+        state[bits] = 16;
+        assert!(chip.read_state(&mut StateReader::new(&state)).is_err());
+    }
+}
@@ -3,7 +3,7 @@ use {
     core::intrinsics::unlikely,
 };
 
-#[derive(PartialEq, Eq, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
 pub enum HdmaState {
     #[default]
     Sleep      = 0,
@@ -13,66 +13,66 @@ pub enum HdmaState {
 }
 
 // IO addresses
-const P1: u8 = 0x00;
-const SB: u8 = 0x01;
-const SC: u8 = 0x02;
-const DIV: u8 = 0x04;
-const TIMA: u8 = 0x05;
-const TMA: u8 = 0x06;
-const TAC: u8 = 0x07;
-const IF: u8 = 0x0F;
-const NR10: u8 = 0x10;
-const NR11: u8 = 0x11;
-const NR12: u8 = 0x12;
-const NR13: u8 = 0x13;
-const NR14: u8 = 0x14;
-const NR21: u8 = 0x16;
-const NR22: u8 = 0x17;
-const NR23: u8 = 0x18;
-const NR24: u8 = 0x19;
-const NR30: u8 = 0x1A;
-const NR31: u8 = 0x1B;
-const NR32: u8 = 0x1C;
-const NR33: u8 = 0x1D;
-const NR34: u8 = 0x1E;
-const NR41: u8 = 0x20;
-const NR42: u8 = 0x21;
-const NR43: u8 = 0x22;
-const NR44: u8 = 0x23;
-const NR50: u8 = 0x24;
-const NR51: u8 = 0x25;
-const NR52: u8 = 0x26;
-const WAV_BEGIN: u8 = 0x30;
-const WAV_END: u8 = 0x3F;
-const LCDC: u8 = 0x40;
-const STAT: u8 = 0x41;
-const SCY: u8 = 0x42;
-const SCX: u8 = 0x43;
-const LY: u8 = 0x44;
-const LYC: u8 = 0x45;
-const DMA: u8 = 0x46;
-const BGP: u8 = 0x47;
-const OBP0: u8 = 0x48;
-const OBP1: u8 = 0x49;
-const WY: u8 = 0x4A;
-const WX: u8 = 0x4B;
-const KEY0: u8 = 0x4C;
-const KEY1: u8 = 0x4D;
-const VBK: u8 = 0x4F;
-const HDMA1: u8 = 0x51;
-const HDMA2: u8 = 0x52;
-const HDMA3: u8 = 0x53;
-const HDMA4: u8 = 0x54;
-const HDMA5: u8 = 0x55;
-const BCPS: u8 = 0x68;
-const BCPD: u8 = 0x69;
-const OCPS: u8 = 0x6A;
-const OCPD: u8 = 0x6B;
-const OPRI: u8 = 0x6C;
-const SVBK: u8 = 0x70;
-const HRAM_BEG: u8 = 0x80;
-const HRAM_END: u8 = 0xFE;
-const IE: u8 = 0xFF;
+pub(crate) const P1: u8 = 0x00;
+pub(crate) const SB: u8 = 0x01;
+pub(crate) const SC: u8 = 0x02;
+pub(crate) const DIV: u8 = 0x04;
+pub(crate) const TIMA: u8 = 0x05;
+pub(crate) const TMA: u8 = 0x06;
+pub(crate) const TAC: u8 = 0x07;
+pub(crate) const IF: u8 = 0x0F;
+pub(crate) const NR10: u8 = 0x10;
+pub(crate) const NR11: u8 = 0x11;
+pub(crate) const NR12: u8 = 0x12;
+pub(crate) const NR13: u8 = 0x13;
+pub(crate) const NR14: u8 = 0x14;
+pub(crate) const NR21: u8 = 0x16;
+pub(crate) const NR22: u8 = 0x17;
+pub(crate) const NR23: u8 = 0x18;
+pub(crate) const NR24: u8 = 0x19;
+pub(crate) const NR30: u8 = 0x1A;
+pub(crate) const NR31: u8 = 0x1B;
+pub(crate) const NR32: u8 = 0x1C;
+pub(crate) const NR33: u8 = 0x1D;
+pub(crate) const NR34: u8 = 0x1E;
+pub(crate) const NR41: u8 = 0x20;
+pub(crate) const NR42: u8 = 0x21;
+pub(crate) const NR43: u8 = 0x22;
+pub(crate) const NR44: u8 = 0x23;
+pub(crate) const NR50: u8 = 0x24;
+pub(crate) const NR51: u8 = 0x25;
+pub(crate) const NR52: u8 = 0x26;
+pub(crate) const WAV_BEGIN: u8 = 0x30;
+pub(crate) const WAV_END: u8 = 0x3F;
+pub(crate) const LCDC: u8 = 0x40;
+pub(crate) const STAT: u8 = 0x41;
+pub(crate) const SCY: u8 = 0x42;
+pub(crate) const SCX: u8 = 0x43;
+pub(crate) const LY: u8 = 0x44;
+pub(crate) const LYC: u8 = 0x45;
+pub(crate) const DMA: u8 = 0x46;
+pub(crate) const BGP: u8 = 0x47;
+pub(crate) const OBP0: u8 = 0x48;
+pub(crate) const OBP1: u8 = 0x49;
+pub(crate) const WY: u8 = 0x4A;
+pub(crate) const WX: u8 = 0x4B;
+pub(crate) const KEY0: u8 = 0x4C;
+pub(crate) const KEY1: u8 = 0x4D;
+pub(crate) const VBK: u8 = 0x4F;
+pub(crate) const HDMA1: u8 = 0x51;
+pub(crate) const HDMA2: u8 = 0x52;
+pub(crate) const HDMA3: u8 = 0x53;
+pub(crate) const HDMA4: u8 = 0x54;
+pub(crate) const HDMA5: u8 = 0x55;
+pub(crate) const BCPS: u8 = 0x68;
+pub(crate) const BCPD: u8 = 0x69;
+pub(crate) const OCPS: u8 = 0x6A;
+pub(crate) const OCPD: u8 = 0x6B;
+pub(crate) const OPRI: u8 = 0x6C;
+pub(crate) const SVBK: u8 = 0x70;
+pub(crate) const HRAM_BEG: u8 = 0x80;
+pub(crate) const HRAM_END: u8 = 0xFE;
+pub(crate) const IE: u8 = 0xFF;
 
 impl Gb {
     #[inline]
@@ -137,11 +137,11 @@ impl Gb {
     }
 
     #[inline]
-    fn read_high(&mut self, addr: u8) -> u8 {
+    pub(crate) fn read_high(&self, addr: u8) -> u8 {
         match addr {
             P1 => self.read_p1(),
             SB => self.sb,
-            SC => self.sc | 0x7E,
+            SC => self.read_sc(),
             DIV => ((self.system_clk >> 8) & 0xFF) as u8,
             TIMA => self.tima,
             TMA => self.tma,
@@ -214,7 +214,7 @@ impl Gb {
         match addr {
             P1 => self.write_joy(val),
             SB => self.sb = val,
-            SC => self.sc = val,
+            SC => self.write_sc(val),
             DIV => self.write_div(),
             TIMA => self.write_tima(val),
             TMA => self.write_tma(val),
@@ -0,0 +1,159 @@
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+/// The banking registers of MMM01 multicarts. They start
+/// unmapped with the menu in the last 32 KiB of the ROM.
+/// The menu selects the outer banks of a game and sets the
+/// map bit, which locks them and makes the game see an
+/// MBC1.
+#[derive(Default)]
+pub(crate) struct Mmm01 {
+    mapped: bool,
+    // 9 bits, the top 4 are only written while unmapped
+    rom_bank: u16,
+    // 4 bits, the top 2 are only written while unmapped
+    ram_bank: u8,
+    // bits 1 to 4 of the ROM bank kept after mapping
+    rom_mask: u8,
+    // bits 0 and 1 of the RAM bank kept after mapping
+    ram_mask: u8,
+    mbc1_mode: bool,
+    mbc1_mode_locked: bool,
+    // RAM bank bits 0 and 1 drive ROM bank bits 5 and 6,
+    // like MBC1 with a big ROM
+    multiplex: bool,
+}
+
+impl Mmm01 {
+    pub(crate) fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if !self.mapped {
+                    self.ram_mask = (val >> 4) & 3;
+                    self.mapped = val & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let writable = if self.mapped {
+                    0x1F & !u16::from(self.rom_mask)
+                } else {
+                    0x7F
+                };
+                self.rom_bank = self.rom_bank & !writable | u16::from(val) & writable;
+            }
+            0x4000..=0x5FFF => {
+                let writable = if self.mapped { 3 & !self.ram_mask } else { 0xF };
+                self.ram_bank = self.ram_bank & !writable | val & writable;
+
+                if !self.mapped {
+                    self.rom_bank = self.rom_bank & 0x7F | u16::from(val & 0x30) << 3;
+                    self.mbc1_mode_locked = val & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !self.mbc1_mode_locked {
+                    self.mbc1_mode = val & 1 != 0;
+                }
+                if !self.mapped {
+                    self.rom_mask = (val & 0x3C) >> 1;
+                    self.multiplex = val & 0x40 != 0;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // the ROM bank and the bits of it switched by the game
+    fn rom_bank(&self) -> (u16, u16) {
+        let mut bank = self.rom_bank;
+        let mut switched = 0x1F & !u16::from(self.rom_mask);
+        if self.multiplex {
+            bank = bank & !0x60 | u16::from(self.ram_bank & 3) << 5;
+            if !self.mbc1_mode {
+                switched |= 0x60;
+            }
+        }
+        (bank, switched)
+    }
+
+    pub(crate) fn rom_offsets(&self, rom_len: usize) -> (usize, usize) {
+        if !self.mapped {
+            return (rom_len - 2 * ROM_BANK_SIZE, rom_len - ROM_BANK_SIZE);
+        }
+
+        let (bank, switched) = self.rom_bank();
+        let lower = bank & !switched;
+        // like MBC1, bank 0 of the game reads as bank 1
+        let upper = if bank & switched & 0x1F == 0 {
+            bank | 1
+        } else {
+            bank
+        };
+
+        (
+            ROM_BANK_SIZE * usize::from(lower),
+            ROM_BANK_SIZE * usize::from(upper),
+        )
+    }
+
+    pub(crate) fn ram_bank(&self) -> usize {
+        let low = match (self.multiplex, self.mbc1_mode) {
+            (false, _) => self.ram_bank & 3,
+            (true, true) => self.rom_bank_mid(),
+            (true, false) => 0,
+        };
+        usize::from(self.ram_bank & 0xC | low)
+    }
+
+    // with multiplexing the bits written as ROM bank bits 5
+    // and 6 select the RAM bank
+    #[allow(clippy::cast_possible_truncation)]
+    fn rom_bank_mid(&self) -> u8 {
+        (self.rom_bank >> 5) as u8 & 3
+    }
+
+    // calls `f` with the writes that rebuild the registers
+    // from the unmapped state, the map bit is set last
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn for_each_write(&self, ram_enable: u8, mut f: impl FnMut(u16, u8)) {
+        f(0x2000, self.rom_bank as u8 & 0x7F);
+        f(
+            0x6000,
+            u8::from(self.mbc1_mode) | self.rom_mask << 1 | u8::from(self.multiplex) << 6,
+        );
+        f(
+            0x4000,
+            self.ram_bank
+                | (self.rom_bank >> 3) as u8 & 0x30
+                | u8::from(self.mbc1_mode_locked) << 6,
+        );
+        f(
+            0x0000,
+            ram_enable | self.ram_mask << 4 | u8::from(self.mapped) << 6,
+        );
+    }
+
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.mapped);
+        w.write_u16(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_u8(self.rom_mask);
+        w.write_u8(self.ram_mask);
+        w.write_bool(self.mbc1_mode);
+        w.write_bool(self.mbc1_mode_locked);
+        w.write_bool(self.multiplex);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.mapped = r.read_bool()?;
+        self.rom_bank = r.read_u16()? & 0x1FF;
+        self.ram_bank = r.read_u8()? & 0xF;
+        self.rom_mask = r.read_u8()? & 0x1E;
+        self.ram_mask = r.read_u8()? & 3;
+        self.mbc1_mode = r.read_bool()?;
+        self.mbc1_mode_locked = r.read_bool()?;
+        self.multiplex = r.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{CompatMode, Gb, IF_LCD_B, IF_VBLANK_B};
+use crate::{
+    savestate::{StateError, StateReader, StateWriter},
+    CompatMode, Gb, IF_LCD_B, IF_VBLANK_B,
+};
 
 /// `GameBoy` screen width in pixels.
 pub const PX_WIDTH: u8 = 160;
@@ -198,6 +201,40 @@ impl ColorPalette {
         self.idx = ((self.idx + 1) & 0x3F) & !mask | self.idx & mask;
     }
 
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.col);
+        w.write_u8(self.idx);
+        w.write_bool(self.inc);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.col)?;
+        self.idx = r.read_u8()? & 0x3F;
+        self.inc = r.read_bool()?;
+        Ok(())
+    }
+
+    // palette RAM as seen by the CPU, two bytes per color
+    pub(crate) fn raw(&self) -> [u8; PAL_RAM_SIZE * 2] {
+        let mut raw = [0; PAL_RAM_SIZE * 2];
+
+        for (i, c) in self.col.chunks_exact(3).enumerate() {
+            raw[i * 2] = c[0] | c[1] << 5;
+            raw[i * 2 + 1] = c[1] >> 3 | c[2] << 2;
+        }
+
+        raw
+    }
+
+    pub(crate) fn set_raw(&mut self, raw: &[u8]) {
+        for (i, c) in raw.chunks_exact(2).take(PAL_RAM_SIZE).enumerate() {
+            let (lo, hi) = (c[0], c[1]);
+            self.col[i * 3] = lo & 0x1F;
+            self.col[i * 3 + 1] = lo >> 5 | (hi & 3) << 3;
+            self.col[i * 3 + 2] = (hi >> 2) & 0x1F;
+        }
+    }
+
     fn rgb(&self, palette: u8, color: u8) -> (u8, u8, u8) {
         fn scale_channel(c: u8) -> u8 {
             (c << 3) | (c >> 2)
@@ -372,6 +409,25 @@ impl Gb {
         };
     }
 
+    // rebuilds the internal PPU counters from the registers,
+    // used when importing states from other emulators
+    pub(crate) fn resync_ppu(&mut self) {
+        self.ly = self.ly.min(153);
+
+        if self.ly >= PX_HEIGHT {
+            self.set_mode(Mode::VBlank);
+        } else if self.ppu_mode() == Mode::VBlank {
+            self.set_mode(Mode::HBlank);
+        }
+
+        self.ppu_cycles = self.ppu_mode().cycles(self.scx);
+        self.frame_dots = i32::from(self.ly) * VBLANK_CYCLES;
+        self.lcdc_delay = false;
+        self.ppu_win_in_frame = false;
+        self.ppu_win_in_ly = false;
+        self.ppu_win_skipped = 0;
+    }
+
     #[inline]
     fn set_mode(&mut self, mode: Mode) {
         self.stat = (self.stat & !STAT_MODE_B) | mode as u8;
@@ -0,0 +1,485 @@
+use {
+    crate::{serial::SerialDevice, Gb},
+    alloc::{boxed::Box, collections::VecDeque, rc::Rc, vec::Vec},
+    core::cell::RefCell,
+};
+
+/// Width in pixels of a printout.
+pub const PRINTER_WIDTH: usize = 160;
+
+const MAGIC_1: u8 = 0x88;
+const MAGIC_2: u8 = 0x33;
+const ALIVE: u8 = 0x81;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_BREAK: u8 = 0x08;
+const CMD_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR_B: u8 = 1;
+const STATUS_BUSY_B: u8 = 2;
+const STATUS_FULL_B: u8 = 4;
+const STATUS_UNPROCESSED_B: u8 = 8;
+const STATUS_PACKET_ERROR_B: u8 = 0x10;
+
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+const TILE_ROW_SIZE: usize = TILES_PER_ROW * 16;
+// 160x144 pixels
+const MAX_IMAGE_SIZE: usize = TILE_ROW_SIZE * 18;
+const MAX_PACKET_DATA: usize = 0x280;
+
+// the printer reports being busy for this number of
+// packets after printing, games wait for it to finish
+const BUSY_PACKETS: u8 = 4;
+
+#[derive(Clone, Copy, Default)]
+enum Stage {
+    #[default]
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LenLo,
+    LenHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    Alive,
+    Status,
+}
+
+/// A printed strip of paper, every pixel is a shade from 0
+/// (white) to 3 (black).
+pub struct Printout {
+    pixels: Vec<u8>,
+}
+
+impl Printout {
+    #[must_use]
+    pub fn width(&self) -> usize {
+        PRINTER_WIDTH
+    }
+
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.pixels.len() / PRINTER_WIDTH
+    }
+
+    /// Row major shades, `width` pixels per row.
+    #[must_use]
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+#[derive(Default)]
+struct State {
+    stage: Stage,
+    command: u8,
+    compressed: bool,
+    len: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+
+    // 2bpp tiles received since the last print
+    image: Vec<u8>,
+    // printed lines, until a print ends with a margin
+    strip: Vec<u8>,
+    printouts: VecDeque<Printout>,
+
+    checksum_error: bool,
+    packet_error: bool,
+    full: bool,
+    busy: u8,
+}
+
+impl State {
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if self.checksum_error {
+            status |= STATUS_CHECKSUM_ERROR_B;
+        }
+        if self.busy > 0 {
+            status |= STATUS_BUSY_B;
+        }
+        if self.full {
+            status |= STATUS_FULL_B;
+        }
+        if !self.image.is_empty() {
+            status |= STATUS_UNPROCESSED_B;
+        }
+        if self.packet_error {
+            status |= STATUS_PACKET_ERROR_B;
+        }
+        status
+    }
+
+    // returns the byte sent back to the GameBoy
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut response = 0;
+
+        self.stage = match self.stage {
+            Stage::Magic1 | Stage::Magic2 if byte == MAGIC_1 => Stage::Magic2,
+            Stage::Magic2 if byte == MAGIC_2 => {
+                self.checksum = 0;
+                Stage::Command
+            }
+            Stage::Magic1 | Stage::Magic2 => Stage::Magic1,
+            Stage::Command => {
+                self.command = byte;
+                Stage::Compression
+            }
+            Stage::Compression => {
+                self.compressed = byte & 1 != 0;
+                Stage::LenLo
+            }
+            Stage::LenLo => {
+                self.len = u16::from(byte);
+                Stage::LenHi
+            }
+            Stage::LenHi => {
+                self.len |= u16::from(byte) << 8;
+                self.data.clear();
+                if self.len == 0 {
+                    Stage::ChecksumLo
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::Data => {
+                // the length is only limited by the protocol,
+                // ignore what can't fit in the buffer
+                if self.data.len() < MAX_PACKET_DATA {
+                    self.data.push(byte);
+                }
+                self.len -= 1;
+                if self.len == 0 {
+                    Stage::ChecksumLo
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::ChecksumLo => {
+                self.received_checksum = u16::from(byte);
+                Stage::ChecksumHi
+            }
+            Stage::ChecksumHi => {
+                self.received_checksum |= u16::from(byte) << 8;
+                Stage::Alive
+            }
+            Stage::Alive => {
+                response = ALIVE;
+                self.run_command();
+                Stage::Status
+            }
+            Stage::Status => {
+                response = self.status();
+                Stage::Magic1
+            }
+        };
+
+        // the checksum covers everything from the command to
+        // the last data byte
+        if matches!(
+            self.stage,
+            Stage::Compression | Stage::LenLo | Stage::LenHi | Stage::Data | Stage::ChecksumLo
+        ) {
+            self.checksum = self.checksum.wrapping_add(u16::from(byte));
+        }
+
+        response
+    }
+
+    fn run_command(&mut self) {
+        self.busy = self.busy.saturating_sub(1);
+
+        self.checksum_error = self.checksum != self.received_checksum;
+        if self.checksum_error {
+            return;
+        }
+
+        self.packet_error = false;
+
+        match self.command {
+            CMD_INIT => {
+                self.image.clear();
+                self.full = false;
+                self.busy = 0;
+            }
+            CMD_DATA if self.data.is_empty() => self.full = true,
+            CMD_DATA => self.receive_data(),
+            CMD_PRINT => {
+                if let [sheets, margins, palette, _exposure] = self.data[..] {
+                    self.print(sheets, margins, palette);
+                } else {
+                    self.packet_error = true;
+                }
+            }
+            CMD_BREAK => {
+                self.image.clear();
+                self.full = false;
+            }
+            CMD_STATUS => (),
+            _ => self.packet_error = true,
+        }
+    }
+
+    // tile data is compressed with runs, a control byte with
+    // the top bit set repeats the next byte (n & 0x7F) + 2
+    // times, otherwise n + 1 literal bytes follow
+    fn receive_data(&mut self) {
+        if self.compressed {
+            let mut bytes = self.data.iter();
+            while let Some(&control) = bytes.next() {
+                if control & 0x80 == 0 {
+                    let len = usize::from(control) + 1;
+                    self.image.extend(bytes.by_ref().take(len));
+                } else {
+                    let len = usize::from(control & 0x7F) + 2;
+                    if let Some(&byte) = bytes.next() {
+                        self.image.extend(core::iter::repeat_n(byte, len));
+                    }
+                }
+            }
+        } else {
+            self.image.extend_from_slice(&self.data);
+        }
+
+        if self.image.len() >= MAX_IMAGE_SIZE {
+            self.image.truncate(MAX_IMAGE_SIZE);
+            self.full = true;
+        }
+    }
+
+    // The top nibble of `margins` is the margin before the
+    // image and the bottom one the margin after it, every
+    // unit is drawn as a blank tile row. The paper isn't cut
+    // until a print ends with a margin, so images printed
+    // in parts end up in the same printout.
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        // a palette of 0 prints as the usual one
+        let palette = if palette == 0 { 0xE4 } else { palette };
+        let blank_rows = |units: u8| usize::from(units) * 8 * PRINTER_WIDTH;
+
+        self.strip
+            .resize(self.strip.len() + blank_rows(margins >> 4), 0);
+
+        // no sheets only feeds paper
+        if sheets > 0 {
+            for tile_row in self.image.chunks_exact(TILE_ROW_SIZE) {
+                for line in 0..8 {
+                    for tile in tile_row.chunks_exact(16) {
+                        let lo = tile[line * 2];
+                        let hi = tile[line * 2 + 1];
+                        for bit in (0..8).rev() {
+                            let color = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+                            self.strip.push((palette >> (color * 2)) & 3);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.strip
+            .resize(self.strip.len() + blank_rows(margins & 0xF), 0);
+
+        if margins & 0xF != 0 && !self.strip.is_empty() {
+            let pixels = core::mem::take(&mut self.strip);
+            self.printouts.push_back(Printout { pixels });
+        }
+
+        self.image.clear();
+        self.full = false;
+        self.busy = BUSY_PACKETS;
+    }
+}
+
+struct PrinterPort {
+    state: Rc<RefCell<State>>,
+}
+
+impl SerialDevice for PrinterPort {
+    fn exchange(&mut self, sent: u8) -> u8 {
+        self.state.borrow_mut().receive(sent)
+    }
+}
+
+/// A Game Boy Printer, connected to the serial port of a
+/// ``GameBoy``. Finished printouts are taken with
+/// `take_printout`.
+#[derive(Default)]
+pub struct Printer {
+    state: Rc<RefCell<State>>,
+}
+
+impl Printer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connects the printer to `gb`, replacing its serial
+    /// device.
+    pub fn connect(&self, gb: &mut Gb) {
+        gb.connect_serial(Box::new(PrinterPort {
+            state: Rc::clone(&self.state),
+        }));
+    }
+
+    /// Returns the oldest printout not taken yet.
+    pub fn take_printout(&mut self) -> Option<Printout> {
+        self.state.borrow_mut().printouts.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            Printer, PrinterPort, ALIVE, CMD_DATA, CMD_PRINT, CMD_STATUS, MAGIC_1, MAGIC_2,
+            PRINTER_WIDTH, STATUS_BUSY_B, STATUS_CHECKSUM_ERROR_B, STATUS_FULL_B,
+            STATUS_UNPROCESSED_B, TILE_ROW_SIZE,
+        },
+        crate::serial::SerialDevice,
+        alloc::{rc::Rc, vec::Vec},
+    };
+
+    // the bytes of a packet, followed by the two bytes
+    // exchanged for the alive and status responses
+    #[allow(clippy::cast_possible_truncation)]
+    fn packet(command: u8, compressed: bool, data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut bytes = alloc::vec![MAGIC_1, MAGIC_2, command, u8::from(compressed)];
+        bytes.extend(len.to_le_bytes());
+        bytes.extend(data);
+        let checksum = bytes[2..]
+            .iter()
+            .fold(0u16, |sum, &b| sum.wrapping_add(u16::from(b)));
+        bytes.extend(checksum.to_le_bytes());
+        bytes.extend([0, 0]);
+        bytes
+    }
+
+    // returns the status sent back
+    fn send(printer: &Printer, bytes: &[u8]) -> u8 {
+        let mut port = PrinterPort {
+            state: Rc::clone(&printer.state),
+        };
+        let responses: Vec<u8> = bytes.iter().map(|&b| port.exchange(b)).collect();
+        let (replies, [alive, status]) = responses.split_at(responses.len() - 2) else {
+            unreachable!()
+        };
+        assert!(replies.iter().all(|&r| r == 0));
+        assert_eq!(*alive, ALIVE);
+        *status
+    }
+
+    fn print(printer: &Printer, margins: u8, palette: u8) -> u8 {
+        send(
+            printer,
+            &packet(CMD_PRINT, false, &[1, margins, palette, 0x40]),
+        )
+    }
+
+    // a row of tiles whose lines all have the colors `lo`
+    // and `hi`
+    fn tile_row(lo: u8, hi: u8) -> Vec<u8> {
+        [lo, hi].repeat(TILE_ROW_SIZE / 2)
+    }
+
+    #[test]
+    fn compressed_data_is_decoded() {
+        let printer = Printer::new();
+        // 3 literals, then 0xAA repeated 4 times
+        let data = [0x02, 1, 2, 3, 0x82, 0xAA];
+        assert_eq!(
+            send(&printer, &packet(CMD_DATA, true, &data)),
+            STATUS_UNPROCESSED_B
+        );
+        assert_eq!(
+            printer.state.borrow().image,
+            [1, 2, 3, 0xAA, 0xAA, 0xAA, 0xAA]
+        );
+    }
+
+    // a whole row of 0x55 in runs of 129 bytes and the
+    // remaining 62
+    #[test]
+    fn long_runs_are_decoded() {
+        let printer = Printer::new();
+        let data = [0xFF, 0x55, 0xFF, 0x55, 0xBC, 0x55];
+        send(&printer, &packet(CMD_DATA, true, &data));
+        assert_eq!(printer.state.borrow().image, [0x55; TILE_ROW_SIZE]);
+    }
+
+    #[test]
+    fn checksum_error() {
+        let printer = Printer::new();
+        let mut bytes = packet(CMD_DATA, false, &tile_row(0xFF, 0));
+        let checksum_lo = bytes.len() - 4;
+        bytes[checksum_lo] ^= 1;
+
+        assert_eq!(send(&printer, &bytes), STATUS_CHECKSUM_ERROR_B);
+        assert!(printer.state.borrow().image.is_empty());
+
+        // cleared by the next valid packet
+        assert_eq!(send(&printer, &packet(CMD_STATUS, false, &[])), 0);
+    }
+
+    #[test]
+    fn status_bits() {
+        let printer = Printer::new();
+        assert_eq!(send(&printer, &packet(CMD_STATUS, false, &[])), 0);
+
+        let row = tile_row(0xFF, 0);
+        assert_eq!(
+            send(&printer, &packet(CMD_DATA, false, &row)),
+            STATUS_UNPROCESSED_B
+        );
+        // an empty data packet ends the image
+        assert_eq!(
+            send(&printer, &packet(CMD_DATA, false, &[])),
+            STATUS_UNPROCESSED_B | STATUS_FULL_B
+        );
+
+        // busy for a few packets after printing
+        assert_eq!(print(&printer, 0x11, 0), STATUS_BUSY_B);
+        for _ in 0..3 {
+            assert_eq!(
+                send(&printer, &packet(CMD_STATUS, false, &[])),
+                STATUS_BUSY_B
+            );
+        }
+        assert_eq!(send(&printer, &packet(CMD_STATUS, false, &[])), 0);
+    }
+
+    #[test]
+    fn strips_are_joined_until_a_margin() {
+        const ROW: usize = 8 * PRINTER_WIDTH;
+
+        let mut printer = Printer::new();
+
+        // color 1 with a margin before, a palette of 0 is
+        // the usual one
+        send(&printer, &packet(CMD_DATA, false, &tile_row(0xFF, 0)));
+        print(&printer, 0x10, 0);
+        assert!(printer.take_printout().is_none());
+
+        // color 3 with a margin after, printed as shade 2
+        send(&printer, &packet(CMD_DATA, false, &tile_row(0xFF, 0xFF)));
+        print(&printer, 0x01, 0x9B);
+
+        let printout = printer.take_printout().unwrap();
+        assert!(printer.take_printout().is_none());
+        assert_eq!(printout.width(), PRINTER_WIDTH);
+        assert_eq!(printout.height(), 32);
+
+        let pixels = printout.pixels();
+        assert!(pixels[..ROW].iter().all(|&p| p == 0));
+        assert!(pixels[ROW..2 * ROW].iter().all(|&p| p == 1));
+        assert!(pixels[2 * ROW..3 * ROW].iter().all(|&p| p == 2));
+        assert!(pixels[3 * ROW..].iter().all(|&p| p == 0));
+    }
+}
@@ -0,0 +1,242 @@
+use {
+    crate::Gb,
+    alloc::{collections::VecDeque, vec::Vec},
+    core::mem::swap,
+};
+
+/// Keeps a bounded history of ``GameBoy`` states to travel
+/// back in time. Only the newest snapshot is stored whole,
+/// older ones are stored as the compressed XOR of two
+/// consecutive snapshots, which is mostly zeros.
+pub struct Rewind {
+    interval: u32,
+    frames: u32,
+    capacity: usize,
+    latest: Vec<u8>,
+    scratch: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Creates a history of `capacity` snapshots, taken
+    /// every `interval` frames.
+    #[must_use]
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            frames: 0,
+            capacity: capacity.max(1),
+            latest: Vec::new(),
+            scratch: Vec::new(),
+            deltas: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Should be called after every emulated frame, takes a
+    /// snapshot every `interval` frames.
+    pub fn push(&mut self, gb: &Gb) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        self.scratch.resize(gb.save_state_size(), 0);
+        if gb.save_state(&mut self.scratch).is_err() {
+            return;
+        }
+
+        if self.latest.len() == self.scratch.len() {
+            // reuse the allocation of the oldest snapshot
+            let mut delta = if self.deltas.len() == self.capacity {
+                self.deltas.pop_front().unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+
+            compress_xor(&self.latest, &self.scratch, &mut delta);
+            self.deltas.push_back(delta);
+        } else {
+            self.deltas.clear();
+        }
+
+        swap(&mut self.latest, &mut self.scratch);
+    }
+
+    /// Goes back one snapshot, returns false when there is
+    /// no history left.
+    pub fn rewind(&mut self, gb: &mut Gb) -> bool {
+        // frames emulated since the last snapshot are undone
+        // first
+        if self.frames == 0 {
+            let Some(delta) = self.deltas.pop_back() else {
+                return false;
+            };
+            decompress_xor(&delta, &mut self.latest);
+        }
+
+        self.frames = 0;
+        gb.load_state(&self.latest).is_ok()
+    }
+
+    /// Forgets every snapshot, should be called after
+    /// loading a state.
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest.clear();
+        self.deltas.clear();
+    }
+}
+
+// The XOR of two snapshots is encoded as pairs of a run of
+// zeros and a run of literal bytes, both lengths are LEB128
+// encoded.
+fn compress_xor(old: &[u8], new: &[u8], out: &mut Vec<u8>) {
+    #[allow(clippy::cast_possible_truncation)]
+    fn write_len(out: &mut Vec<u8>, mut len: usize) {
+        while len >= 0x80 {
+            out.push((len & 0x7F) as u8 | 0x80);
+            len >>= 7;
+        }
+        out.push(len as u8);
+    }
+
+    out.clear();
+
+    let len = old.len().min(new.len());
+    let mut i = 0;
+
+    while i < len {
+        let zeros_start = i;
+        while i < len && old[i] == new[i] {
+            i += 1;
+        }
+
+        let literals_start = i;
+        while i < len && old[i] != new[i] {
+            i += 1;
+        }
+
+        write_len(out, literals_start - zeros_start);
+        write_len(out, i - literals_start);
+        out.extend(
+            old[literals_start..i]
+                .iter()
+                .zip(&new[literals_start..i])
+                .map(|(a, b)| a ^ b),
+        );
+    }
+}
+
+fn decompress_xor(delta: &[u8], state: &mut [u8]) {
+    fn read_len(delta: &mut core::slice::Iter<u8>) -> usize {
+        let mut len = 0;
+        let mut shift = 0;
+        for &byte in delta {
+            len |= usize::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        len
+    }
+
+    let mut delta = delta.iter();
+    let mut pos = 0;
+
+    while delta.len() > 0 {
+        pos += read_len(&mut delta);
+        let literals = read_len(&mut delta);
+        for (dst, src) in state[pos..pos + literals].iter_mut().zip(&mut delta) {
+            *dst ^= src;
+        }
+        pos += literals;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{compress_xor, decompress_xor, Rewind},
+        crate::{Cartridge, Gb, Model},
+        alloc::{boxed::Box, vec::Vec},
+    };
+
+    fn round_trip(old: &[u8], new: &[u8]) {
+        let mut delta = Vec::new();
+        compress_xor(old, new, &mut delta);
+        let mut state = old.to_vec();
+        decompress_xor(&delta, &mut state);
+        assert_eq!(state, new);
+    }
+
+    #[test]
+    fn xor_round_trip() {
+        let old: Vec<u8> = (0..=255).cycle().take(1000).collect();
+
+        round_trip(&old, &old);
+        let inverted: Vec<u8> = old.iter().map(|b| !b).collect();
+        round_trip(&old, &inverted);
+
+        // runs of 127 and 128 bytes take one and two bytes
+        // to encode their length
+        for run in [127, 128] {
+            for start in [0, 1, 500, 1000 - run] {
+                let mut changed = old.clone();
+                changed[start..start + run]
+                    .iter_mut()
+                    .for_each(|b| *b ^= 0x5A);
+                round_trip(&old, &changed);
+
+                let mut kept = inverted.clone();
+                kept[start..start + run].copy_from_slice(&old[start..start + run]);
+                round_trip(&old, &kept);
+            }
+        }
+    }
+
+    #[test]
+    fn identical_states_compress_to_one_run() {
+        let state = alloc::vec![0x12; 1000];
+        let mut delta = Vec::new();
+        compress_xor(&state, &state, &mut delta);
+        // 1000 zeros and no literals
+        assert_eq!(delta, [0xE8, 0x07, 0x00]);
+    }
+
+    fn gb() -> Box<Gb> {
+        let mut rom = alloc::vec![0; 0x8000];
+        // inc a; jr -3
+        rom[0x100..0x103].copy_from_slice(&[0x3C, 0x18, 0xFD]);
+        let cart = Cartridge::new(&rom, None).unwrap();
+        Gb::new(Model::Cgb, cart, |_, _| (), 48000)
+    }
+
+    fn save(gb: &Gb) -> Vec<u8> {
+        let mut buf = alloc::vec![0; gb.save_state_size()];
+        gb.save_state(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn rewind_after_wrapping() {
+        const CAPACITY: usize = 4;
+
+        let mut gb = gb();
+        let mut rewind = Rewind::new(1, CAPACITY);
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            gb.run_frame();
+            rewind.push(&gb);
+            states.push(save(&gb));
+        }
+
+        // the oldest snapshots were dropped
+        for n in 1..=CAPACITY {
+            assert!(rewind.rewind(&mut gb));
+            assert_eq!(save(&gb), states[states.len() - 1 - n]);
+        }
+        assert!(!rewind.rewind(&mut gb));
+    }
+}
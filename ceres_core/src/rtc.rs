@@ -0,0 +1,285 @@
+use crate::{
+    savestate::{StateError, StateReader, StateWriter},
+    TC_SEC,
+};
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+const DH_DAY_HI_B: u8 = 1;
+const DH_HALT_B: u8 = 0x40;
+const DH_CARRY_B: u8 = 0x80;
+
+/// Size of the clock appended to save files by VBA and BGB.
+pub(crate) const RTC_TRAILER_SIZE: usize = 48;
+// older versions store a 32 bit timestamp
+pub(crate) const RTC_TRAILER_SIZE_32: usize = 44;
+
+#[derive(Clone, Copy, Default)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_lo: u8,
+    // day bit 8, halt and day carry
+    dh: u8,
+}
+
+impl Registers {
+    fn read(self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds & 0x3F,
+            0x09 => self.minutes & 0x3F,
+            0x0A => self.hours & 0x1F,
+            0x0B => self.day_lo,
+            0x0C => self.dh & (DH_DAY_HI_B | DH_HALT_B | DH_CARRY_B),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, reg: u8, val: u8) {
+        match reg {
+            0x08 => self.seconds = val & 0x3F,
+            0x09 => self.minutes = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.day_lo = val,
+            0x0C => self.dh = val & (DH_DAY_HI_B | DH_HALT_B | DH_CARRY_B),
+            _ => (),
+        }
+    }
+
+    fn halted(self) -> bool {
+        self.dh & DH_HALT_B != 0
+    }
+
+    fn days(self) -> u16 {
+        u16::from(self.dh & DH_DAY_HI_B) << 8 | u16::from(self.day_lo)
+    }
+
+    fn set_days(&mut self, days: u16) {
+        let [lo, hi] = days.to_le_bytes();
+        self.day_lo = lo;
+        self.dh = self.dh & !DH_DAY_HI_B | hi & DH_DAY_HI_B;
+    }
+
+    // Counters hold out of range values if written, they
+    // wrap at their bit width without carrying into the
+    // next one.
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.tick_day();
+    }
+
+    fn tick_day(&mut self) {
+        self.add_days(1);
+    }
+
+    // the day counter has 9 bits, the carry is set when it
+    // overflows
+    fn add_days(&mut self, days: u64) {
+        let days = u64::from(self.days()) + days;
+        if days > 0x1FF {
+            self.dh |= DH_CARRY_B;
+        }
+
+        // masked to 9 bits
+        #[allow(clippy::cast_possible_truncation)]
+        self.set_days((days & 0x1FF) as u16);
+    }
+
+    fn time_valid(self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        if self.halted() {
+            return;
+        }
+
+        // Out of range counters are ticked until they wrap,
+        // a few hours at most. Then whole days only change
+        // the day counter and the rest is ticked.
+        while seconds > 0 && !self.time_valid() {
+            self.tick_second();
+            seconds -= 1;
+        }
+
+        self.add_days(seconds / SECONDS_PER_DAY);
+
+        for _ in 0..seconds % SECONDS_PER_DAY {
+            self.tick_second();
+        }
+    }
+}
+
+/// The real time clock of MBC3 cartridges.
+#[derive(Default)]
+pub(crate) struct Rtc {
+    regs: Registers,
+    latched: Registers,
+    latch_armed: bool,
+    // normal speed t-cycles since the last second
+    cycles: u32,
+    // time the save file was written, to catch up with the
+    // time passed since then
+    saved_at: Option<u64>,
+}
+
+impl Rtc {
+    pub(crate) fn run(&mut self, cycles: u32) {
+        if self.regs.halted() {
+            return;
+        }
+
+        self.cycles += cycles;
+        while self.cycles >= TC_SEC {
+            self.cycles -= TC_SEC;
+            self.regs.tick_second();
+        }
+    }
+
+    // writing 0 and then 1 copies the counters to the
+    // registers read by the CPU
+    pub(crate) fn write_latch(&mut self, val: u8) {
+        if self.latch_armed && val == 1 {
+            self.latched = self.regs;
+        }
+        self.latch_armed = val == 0;
+    }
+
+    pub(crate) fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    pub(crate) fn write(&mut self, reg: u8, val: u8) {
+        // writing the seconds resets the divider
+        if reg == 0x08 {
+            self.cycles = 0;
+        }
+        self.regs.write(reg, val);
+    }
+
+    /// Advances the clock by the seconds passed since the
+    /// save file was written, if it stored the time.
+    pub(crate) fn catch_up(&mut self, unix_time: u64) {
+        if let Some(saved_at) = self.saved_at.take() {
+            self.regs.advance(unix_time.saturating_sub(saved_at));
+        }
+    }
+
+    // Every register takes 4 bytes, the counters are
+    // followed by the latched registers and a little endian
+    // timestamp of 8 bytes, or 4 in older versions.
+    pub(crate) fn read_trailer(&mut self, trailer: &[u8]) {
+        let regs = |first: usize| {
+            let mut regs = Registers::default();
+            for (i, reg) in (0x08..=0x0C).enumerate() {
+                regs.write(reg, trailer[(first + i) * 4]);
+            }
+            regs
+        };
+
+        self.regs = regs(0);
+        self.latched = regs(5);
+
+        let mut timestamp = [0; 8];
+        let len = trailer.len() - 40;
+        timestamp[..len].copy_from_slice(&trailer[40..]);
+        self.saved_at = Some(u64::from_le_bytes(timestamp));
+    }
+
+    pub(crate) fn write_trailer(&self, unix_time: u64) -> [u8; RTC_TRAILER_SIZE] {
+        let mut trailer = [0; RTC_TRAILER_SIZE];
+
+        for (i, regs) in [self.regs, self.latched].iter().enumerate() {
+            let bytes = [regs.seconds, regs.minutes, regs.hours, regs.day_lo, regs.dh];
+            for (j, byte) in bytes.into_iter().enumerate() {
+                trailer[(i * 5 + j) * 4] = byte;
+            }
+        }
+
+        trailer[40..].copy_from_slice(&unix_time.to_le_bytes());
+        trailer
+    }
+
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        for regs in [self.regs, self.latched] {
+            w.write_u8(regs.seconds);
+            w.write_u8(regs.minutes);
+            w.write_u8(regs.hours);
+            w.write_u8(regs.day_lo);
+            w.write_u8(regs.dh);
+        }
+        w.write_bool(self.latch_armed);
+        w.write_u32(self.cycles);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for regs in [&mut self.regs, &mut self.latched] {
+            regs.seconds = r.read_u8()?;
+            regs.minutes = r.read_u8()?;
+            regs.hours = r.read_u8()?;
+            regs.day_lo = r.read_u8()?;
+            regs.dh = r.read_u8()?;
+        }
+        self.latch_armed = r.read_bool()?;
+        self.cycles = r.read_u32()?.min(TC_SEC - 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registers;
+
+    fn invalid() -> Registers {
+        Registers {
+            seconds: 0x3F,
+            minutes: 0x3F,
+            hours: 0x1F,
+            day_lo: 0x10,
+            dh: 0,
+        }
+    }
+
+    #[test]
+    fn advance_matches_ticking() {
+        let mut ticked = invalid();
+        for _ in 0..300_000 {
+            ticked.tick_second();
+        }
+
+        let mut advanced = invalid();
+        advanced.advance(300_000);
+
+        assert_eq!(ticked.read(0x08), advanced.read(0x08));
+        assert_eq!(ticked.read(0x09), advanced.read(0x09));
+        assert_eq!(ticked.read(0x0A), advanced.read(0x0A));
+        assert_eq!(ticked.days(), advanced.days());
+    }
+
+    #[test]
+    fn advance_bogus_time_is_fast() {
+        let mut regs = invalid();
+        regs.advance(u64::MAX / 2);
+
+        assert!(regs.time_valid());
+        assert_eq!(regs.read(0x0C) & 0x80, 0x80);
+    }
+}
@@ -0,0 +1,563 @@
+use crate::{memory::HdmaState, ppu::Mode, CompatMode, Gb, Model};
+
+const STATE_MAGIC: [u8; 4] = *b"CERS";
+// bump every time the layout of the snapshot changes
+const STATE_VERSION: u16 = 11;
+
+/// Represents a save state error.
+#[derive(Debug)]
+pub enum StateError {
+    BufferTooSmall,
+    InvalidMagic,
+    UnsupportedVersion,
+    RomMismatch,
+    InvalidSize,
+    InvalidValue,
+}
+
+// All values are stored in little endian so snapshots can
+// be moved between machines.
+pub(crate) struct StateWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> StateWriter<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    // writes past the end of the buffer are dropped but still
+    // counted, that way we can compute the size of a
+    // snapshot
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        if let Some(dst) = self.buf.get_mut(self.pos..self.pos + bytes.len()) {
+            dst.copy_from_slice(bytes);
+        }
+
+        self.pos += bytes.len();
+    }
+
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn write_u8(&mut self, val: u8) {
+        self.write_bytes(&[val]);
+    }
+
+    pub(crate) fn write_bool(&mut self, val: bool) {
+        self.write_u8(u8::from(val));
+    }
+
+    pub(crate) fn write_u16(&mut self, val: u16) {
+        self.write_bytes(&val.to_le_bytes());
+    }
+
+    pub(crate) fn write_u32(&mut self, val: u32) {
+        self.write_bytes(&val.to_le_bytes());
+    }
+
+    pub(crate) fn write_i32(&mut self, val: i32) {
+        self.write_bytes(&val.to_le_bytes());
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub(crate) fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn read_bytes(&mut self, dst: &mut [u8]) -> Result<(), StateError> {
+        let src = self
+            .buf
+            .get(self.pos..self.pos + dst.len())
+            .ok_or(StateError::InvalidSize)?;
+        dst.copy_from_slice(src);
+        self.pos += dst.len();
+        Ok(())
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, StateError> {
+        let mut bytes = [0; 1];
+        self.read_bytes(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    pub(crate) fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        self.read_bytes(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        self.read_bytes(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn read_i32(&mut self) -> Result<i32, StateError> {
+        let mut bytes = [0; 4];
+        self.read_bytes(&mut bytes)?;
+        Ok(i32::from_le_bytes(bytes))
+    }
+}
+
+impl Model {
+    fn from_state(val: u8) -> Result<Self, StateError> {
+        match val {
+            0 => Ok(Self::Dmg),
+            1 => Ok(Self::Mgb),
+            2 => Ok(Self::Cgb),
+            _ => Err(StateError::InvalidValue),
+        }
+    }
+}
+
+impl CompatMode {
+    fn from_state(val: u8) -> Result<Self, StateError> {
+        match val {
+            0 => Ok(Self::Dmg),
+            1 => Ok(Self::Compat),
+            2 => Ok(Self::Cgb),
+            _ => Err(StateError::InvalidValue),
+        }
+    }
+}
+
+impl HdmaState {
+    fn from_state(val: u8) -> Result<Self, StateError> {
+        match val {
+            0 => Ok(Self::Sleep),
+            1 => Ok(Self::HBlank),
+            2 => Ok(Self::HBlankDone),
+            3 => Ok(Self::General),
+            _ => Err(StateError::InvalidValue),
+        }
+    }
+}
+
+impl Gb {
+    /// Returns the size in bytes of a save state of this
+    /// ``GameBoy``. It only depends on the loaded
+    /// cartridge.
+    #[must_use]
+    pub fn save_state_size(&self) -> usize {
+        let mut w = StateWriter::new(&mut []);
+        self.write_state(&mut w);
+        w.pos
+    }
+
+    /// Writes a snapshot of the whole emulator state into
+    /// `buf` and returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `buf` is smaller than
+    /// `save_state_size`.
+    pub fn save_state(&self, buf: &mut [u8]) -> Result<usize, StateError> {
+        if buf.len() < self.save_state_size() {
+            return Err(StateError::BufferTooSmall);
+        }
+
+        let mut w = StateWriter::new(buf);
+        self.write_state(&mut w);
+        Ok(w.pos)
+    }
+
+    /// Restores a snapshot previously written by
+    /// `save_state`. The emulator is left untouched if the
+    /// snapshot is rejected.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot is corrupt, was
+    /// made by an incompatible version or belongs to
+    /// another ROM.
+    pub fn load_state(&mut self, buf: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(buf);
+
+        let mut magic = [0; 4];
+        r.read_bytes(&mut magic)?;
+        if magic != STATE_MAGIC {
+            return Err(StateError::InvalidMagic);
+        }
+
+        if r.read_u16()? != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion);
+        }
+
+        let mut checksum = [0; 3];
+        r.read_bytes(&mut checksum)?;
+        if checksum != self.cart.header_checksum() {
+            return Err(StateError::RomMismatch);
+        }
+
+        if buf.len() != self.save_state_size() {
+            return Err(StateError::InvalidSize);
+        }
+
+        // Values are validated as they are read, the current
+        // state is kept to roll back to if one is invalid.
+        let mut backup = alloc::vec![0; buf.len()];
+        self.write_state(&mut StateWriter::new(&mut backup));
+        let dirty = self.cartridge_ram_dirty();
+        let start = r.pos();
+
+        if let Err(e) = self.read_snapshot(&mut r) {
+            let mut backup_r = StateReader::new(&backup);
+            backup_r.seek(start);
+            // our own snapshot is always valid
+            let restored = self.read_snapshot(&mut backup_r);
+            debug_assert!(restored.is_ok());
+            if !dirty {
+                self.mark_cartridge_ram_saved();
+            }
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn read_snapshot(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.model = Model::from_state(r.read_u8()?)?;
+        self.compat_mode = CompatMode::from_state(r.read_u8()?)?;
+        self.read_state(r)
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&STATE_MAGIC);
+        w.write_u16(STATE_VERSION);
+        w.write_bytes(&self.cart.header_checksum());
+
+        // general
+        w.write_u8(self.model as u8);
+        w.write_u8(self.compat_mode as u8);
+        w.write_bool(self.double_speed);
+        w.write_u8(self.key1);
+        w.write_bool(self.boot_rom.is_some());
+
+        // cartridge
+        self.cart.write_state(w);
+
+        // cpu
+        w.write_u16(self.af);
+        w.write_u16(self.bc);
+        w.write_u16(self.de);
+        w.write_u16(self.hl);
+        w.write_u16(self.sp);
+        w.write_u16(self.pc);
+        w.write_i32(self.stolen_cycles);
+        w.write_bool(self.cpu_ei_delay);
+        w.write_bool(self.cpu_halted);
+
+        // serial
+        w.write_u8(self.sb);
+        w.write_u8(self.sc);
+        w.write_u8(self.serial_bits);
+        w.write_u8(self.serial_in);
+
+        // joypad, pressed buttons belong to the frontend
+        w.write_bool(self.p1_dirs);
+        w.write_bool(self.p1_acts);
+
+        // interrupts
+        w.write_bool(self.halt_bug);
+        w.write_bool(self.ime);
+        w.write_u8(self.ifr);
+        w.write_u8(self.ie);
+
+        // memory
+        w.write_bytes(&self.wram);
+        w.write_bytes(&self.hram);
+        w.write_u8(self.svbk);
+        w.write_u8(self.svbk_true);
+
+        // -- dma
+        w.write_u8(self.dma);
+        w.write_bool(self.dma_on);
+        w.write_u16(self.dma_addr);
+        w.write_bool(self.dma_restarting);
+        w.write_i32(self.dma_cycles);
+
+        // -- hdma
+        w.write_u8(self.hdma5);
+        w.write_u16(self.hdma_src);
+        w.write_u16(self.hdma_dst);
+        w.write_u16(self.hdma_len);
+        w.write_u8(self.hdma_state as u8);
+
+        // ppu
+        w.write_u8(self.lcdc);
+        w.write_u8(self.stat);
+        w.write_u8(self.scy);
+        w.write_u8(self.scx);
+        w.write_u8(self.ly);
+        w.write_u8(self.lyc);
+        w.write_u8(self.bgp);
+        w.write_u8(self.obp0);
+        w.write_u8(self.obp1);
+        w.write_u8(self.wy);
+        w.write_u8(self.wx);
+        w.write_u8(self.opri);
+        w.write_u8(self.vbk);
+        self.bcp.write_state(w);
+        self.ocp.write_state(w);
+        w.write_i32(self.frame_dots);
+        w.write_bool(self.lcdc_delay);
+        w.write_bytes(&self.vram);
+        w.write_bytes(&self.oam);
+        w.write_i32(self.ppu_cycles);
+        w.write_bool(self.ppu_win_in_frame);
+        w.write_bool(self.ppu_win_in_ly);
+        w.write_u8(self.ppu_win_skipped);
+
+        // clock
+        w.write_u8(self.tima);
+        w.write_u8(self.tma);
+        w.write_u8(self.tac);
+        w.write_bool(self.tac_enable);
+        w.write_u16(self.system_clk);
+
+        // apu
+        w.write_u8(self.nr51);
+        w.write_bool(self.apu_on);
+        w.write_u8(self.apu_r_vol);
+        w.write_u8(self.apu_l_vol);
+        w.write_bool(self.apu_r_vin);
+        w.write_bool(self.apu_l_vin);
+        self.apu_ch1.write_state(w);
+        self.apu_ch2.write_state(w);
+        self.apu_ch3.write_state(w);
+        self.apu_ch4.write_state(w);
+        w.write_u16(self.apu_timer);
+        w.write_u32(self.apu_render_timer);
+        w.write_u8(self.apu_seq_step);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        // general
+        self.double_speed = r.read_bool()?;
+        self.key1 = r.read_u8()?;
+        self.boot_rom = r.read_bool()?.then_some(self.model.boot_rom());
+
+        // cartridge
+        self.cart.read_state(r)?;
+
+        // cpu
+        self.af = r.read_u16()?;
+        self.bc = r.read_u16()?;
+        self.de = r.read_u16()?;
+        self.hl = r.read_u16()?;
+        self.sp = r.read_u16()?;
+        self.pc = r.read_u16()?;
+        self.stolen_cycles = r.read_i32()?;
+        self.cpu_ei_delay = r.read_bool()?;
+        self.cpu_halted = r.read_bool()?;
+
+        // serial
+        self.sb = r.read_u8()?;
+        self.sc = r.read_u8()?;
+        self.serial_bits = r.read_u8()?.min(8);
+        self.serial_in = r.read_u8()?;
+
+        // joypad
+        self.p1_dirs = r.read_bool()?;
+        self.p1_acts = r.read_bool()?;
+
+        // interrupts
+        self.halt_bug = r.read_bool()?;
+        self.ime = r.read_bool()?;
+        self.ifr = r.read_u8()?;
+        self.ie = r.read_u8()?;
+
+        // memory
+        r.read_bytes(&mut self.wram)?;
+        r.read_bytes(&mut self.hram)?;
+        self.svbk = r.read_u8()? & 7;
+        self.svbk_true = r.read_u8()? & 7;
+
+        // -- dma
+        self.dma = r.read_u8()?;
+        self.dma_on = r.read_bool()?;
+        self.dma_addr = r.read_u16()?;
+        self.dma_restarting = r.read_bool()?;
+        self.dma_cycles = r.read_i32()?;
+
+        // -- hdma
+        self.hdma5 = r.read_u8()?;
+        self.hdma_src = r.read_u16()?;
+        self.hdma_dst = r.read_u16()?;
+        self.hdma_len = r.read_u16()?;
+        self.hdma_state = HdmaState::from_state(r.read_u8()?)?;
+
+        // ppu
+        self.lcdc = r.read_u8()?;
+        self.stat = r.read_u8()?;
+        self.scy = r.read_u8()?;
+        self.scx = r.read_u8()?;
+        self.ly = r.read_u8()?;
+        // LY 144..=153 is exactly the VBlank period
+        if self.ly > 153 || (self.ly >= 144) != (self.ppu_mode() == Mode::VBlank) {
+            return Err(StateError::InvalidValue);
+        }
+        self.lyc = r.read_u8()?;
+        self.bgp = r.read_u8()?;
+        self.obp0 = r.read_u8()?;
+        self.obp1 = r.read_u8()?;
+        self.wy = r.read_u8()?;
+        self.wx = r.read_u8()?;
+        self.opri = r.read_u8()?;
+        self.vbk = r.read_u8()? & 1;
+        self.bcp.read_state(r)?;
+        self.ocp.read_state(r)?;
+        self.frame_dots = r.read_i32()?;
+        self.lcdc_delay = r.read_bool()?;
+        r.read_bytes(&mut self.vram)?;
+        r.read_bytes(&mut self.oam)?;
+        self.ppu_cycles = r.read_i32()?;
+        self.ppu_win_in_frame = r.read_bool()?;
+        self.ppu_win_in_ly = r.read_bool()?;
+        self.ppu_win_skipped = r.read_u8()?;
+
+        // clock
+        self.tima = r.read_u8()?;
+        self.tma = r.read_u8()?;
+        self.tac = r.read_u8()?;
+        self.tac_enable = r.read_bool()?;
+        self.system_clk = r.read_u16()?;
+
+        // apu
+        self.nr51 = r.read_u8()?;
+        self.apu_on = r.read_bool()?;
+        self.apu_r_vol = r.read_u8()?;
+        self.apu_l_vol = r.read_u8()?;
+        self.apu_r_vin = r.read_bool()?;
+        self.apu_l_vin = r.read_bool()?;
+        self.apu_ch1.read_state(r)?;
+        self.apu_ch2.read_state(r)?;
+        self.apu_ch3.read_state(r)?;
+        self.apu_ch4.read_state(r)?;
+        self.apu_timer = r.read_u16()?;
+        self.apu_render_timer = r.read_u32()?;
+        self.apu_seq_step = r.read_u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{memory::HdmaState, Cartridge, Gb, Model},
+        alloc::{boxed::Box, vec::Vec},
+    };
+
+    fn gb() -> Box<Gb> {
+        let mut rom = alloc::vec![0; 0x8000];
+        // inc a; jr -3
+        rom[0x100..0x103].copy_from_slice(&[0x3C, 0x18, 0xFD]);
+        // MBC1 + RAM + battery, 32 KiB ROM, 8 KiB RAM
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let cart = Cartridge::new(&rom, None).unwrap();
+        Gb::new(Model::Cgb, cart, |_, _| (), 48000)
+    }
+
+    fn save(gb: &Gb) -> Vec<u8> {
+        let mut buf = alloc::vec![0; gb.save_state_size()];
+        gb.save_state(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut gb = gb();
+        for _ in 0..30 {
+            gb.run_frame();
+        }
+        gb.cart.mut_ram().fill(0xAB);
+        let state = save(&gb);
+
+        for _ in 0..30 {
+            gb.run_frame();
+        }
+        gb.cart.mut_ram().fill(0);
+        assert_ne!(state, save(&gb));
+
+        gb.load_state(&state).unwrap();
+        assert_eq!(state, save(&gb));
+        assert_eq!(gb.cart.ram()[0], 0xAB);
+    }
+
+    #[test]
+    fn invalid_state_is_rejected_whole() {
+        let mut gb = gb();
+        for _ in 0..30 {
+            gb.run_frame();
+        }
+
+        // the only byte that changes is the HDMA state
+        gb.hdma_state = HdmaState::Sleep;
+        let sleep = save(&gb);
+        gb.hdma_state = HdmaState::General;
+        let general = save(&gb);
+        let hdma_pos = (0..sleep.len()).find(|&i| sleep[i] != general[i]).unwrap();
+        gb.hdma_state = HdmaState::Sleep;
+
+        for _ in 0..30 {
+            gb.run_frame();
+        }
+        gb.mark_cartridge_ram_saved();
+        let before = save(&gb);
+
+        let mut invalid = sleep;
+        invalid[hdma_pos] = 0xFF;
+        assert!(gb.load_state(&invalid).is_err());
+        assert_eq!(before, save(&gb));
+        assert!(!gb.cartridge_ram_dirty());
+    }
+
+    #[test]
+    fn invalid_ly_is_rejected() {
+        let mut gb = gb();
+        for _ in 0..30 {
+            gb.run_frame();
+        }
+        gb.mark_cartridge_ram_saved();
+        let before = save(&gb);
+
+        // LY is the only byte that changes
+        let ly = gb.ly;
+        gb.ly = ly ^ 0xFF;
+        let marked = save(&gb);
+        gb.ly = ly;
+        let ly_pos = (0..before.len()).find(|&i| before[i] != marked[i]).unwrap();
+        let vblank = gb.stat & 3 == 1;
+
+        let mut invalid = before.clone();
+        for val in [154, 0xFF, if vblank { 0 } else { 144 }] {
+            invalid[ly_pos] = val;
+            assert!(gb.load_state(&invalid).is_err());
+            assert_eq!(before, save(&gb));
+            assert!(!gb.cartridge_ram_dirty());
+        }
+    }
+}
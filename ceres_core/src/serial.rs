@@ -0,0 +1,192 @@
+use {
+    crate::{Gb, Model::Cgb, IF_SERIAL_B},
+    alloc::boxed::Box,
+};
+
+const SC_START_B: u8 = 0x80;
+const SC_FAST_CLOCK_B: u8 = 2;
+const SC_INTERNAL_CLOCK_B: u8 = 1;
+
+// the serial clock ticks on the falling edges of a system
+// clock bit, 8192 Hz or 262144 Hz with the CGB fast clock
+const SERIAL_CLOCK_BIT: u16 = 1 << 8;
+const SERIAL_FAST_CLOCK_BIT: u16 = 1 << 3;
+
+/// A device connected to the serial port, like another
+/// ``GameBoy`` through a link cable or a printer.
+pub trait SerialDevice {
+    /// Called when the ``GameBoy`` starts a transfer with
+    /// its internal clock. Receives the byte sent and
+    /// returns the byte shifted in during the transfer.
+    fn exchange(&mut self, sent: u8) -> u8;
+
+    /// Polled while the ``GameBoy`` waits for a transfer
+    /// clocked by the device. Returns the byte shifted in
+    /// if the device clocks a transfer, which receives
+    /// `sent`.
+    fn poll_external(&mut self, sent: u8) -> Option<u8> {
+        let _ = sent;
+        None
+    }
+}
+
+impl Gb {
+    /// Connects `device` to the serial port, replacing the
+    /// previous one.
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial_device = Some(device);
+    }
+
+    /// Disconnects the device connected to the serial port,
+    /// if any. Transfers shift in ones afterwards, as
+    /// when no cable is connected.
+    pub fn disconnect_serial(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.serial_device.take()
+    }
+
+    /// Returns the byte in SB if the ``GameBoy`` waits for
+    /// a transfer clocked by the other side, used by
+    /// devices to know what to receive.
+    #[must_use]
+    pub fn waiting_serial_byte(&self) -> Option<u8> {
+        (self.sc & (SC_START_B | SC_INTERNAL_CLOCK_B) == SC_START_B).then_some(self.sb)
+    }
+
+    #[must_use]
+    pub(crate) fn read_sc(&self) -> u8 {
+        if self.model == Cgb {
+            self.sc | 0x7C
+        } else {
+            self.sc | 0x7E
+        }
+    }
+
+    pub(crate) fn write_sc(&mut self, val: u8) {
+        self.sc = if self.model == Cgb {
+            val & 0x83
+        } else {
+            val & 0x81
+        };
+        self.serial_bits = 0;
+
+        if self.sc & (SC_START_B | SC_INTERNAL_CLOCK_B) == SC_START_B | SC_INTERNAL_CLOCK_B {
+            self.check_blargg_result(self.sb);
+
+            if let Some(serial_callback) = self.serial_callback {
+                serial_callback(self.sb);
+            }
+
+            // the byte of the other side is known from the
+            // beginning, but it's shifted in bit by bit
+            self.serial_in = self
+                .serial_device
+                .as_mut()
+                .map_or(0xFF, |device| device.exchange(self.sb));
+            self.serial_bits = 8;
+        }
+    }
+
+    // called every t-cycle with the system clock before the
+    // increment
+    pub(crate) fn run_serial(&mut self, old_system_clk: u16) {
+        if self.serial_bits == 0 {
+            return;
+        }
+
+        let bit = if self.sc & SC_FAST_CLOCK_B == 0 {
+            SERIAL_CLOCK_BIT
+        } else {
+            SERIAL_FAST_CLOCK_BIT
+        };
+
+        // falling edge
+        if old_system_clk & bit == 0 || self.system_clk & bit != 0 {
+            return;
+        }
+
+        self.sb = self.sb << 1 | self.serial_in >> 7;
+        self.serial_in <<= 1;
+        self.serial_bits -= 1;
+
+        if self.serial_bits == 0 {
+            self.finish_serial_transfer();
+        }
+    }
+
+    // transfers clocked by the other side are completed at
+    // once, a bit by bit exchange would need both sides to
+    // run in lockstep every t-cycle
+    pub(crate) fn poll_serial_device(&mut self) {
+        if self.sc & (SC_START_B | SC_INTERNAL_CLOCK_B) != SC_START_B {
+            return;
+        }
+
+        let sb = self.sb;
+        if let Some(received) = self
+            .serial_device
+            .as_mut()
+            .and_then(|device| device.poll_external(sb))
+        {
+            self.check_blargg_result(sb);
+
+            if let Some(serial_callback) = self.serial_callback {
+                serial_callback(sb);
+            }
+
+            self.sb = received;
+            self.finish_serial_transfer();
+        }
+    }
+
+    fn finish_serial_transfer(&mut self) {
+        self.sc &= !SC_START_B;
+        self.ifr |= IF_SERIAL_B;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{SC_FAST_CLOCK_B, SC_INTERNAL_CLOCK_B, SC_START_B},
+        crate::{Cartridge, Gb, Model, IF_SERIAL_B},
+        alloc::boxed::Box,
+    };
+
+    fn gb() -> Box<Gb> {
+        let cart = Cartridge::new(&alloc::vec![0; 0x8000], None).unwrap();
+        let mut gb = Gb::new(Model::Cgb, cart, |_, _| (), 48000);
+        gb.system_clk = 0;
+        gb.ifr = 0;
+        gb.sb = 0x42;
+        gb
+    }
+
+    // 8 bits, clocked on the falling edges of a system
+    // clock bit
+    fn transfer(sc: u8, cycles: i32) {
+        let mut gb = gb();
+        gb.write_sc(sc);
+
+        gb.run_timers(cycles - 1);
+        assert_ne!(gb.sc & SC_START_B, 0);
+        assert_eq!(gb.ifr & IF_SERIAL_B, 0);
+
+        gb.run_timers(1);
+        assert_eq!(gb.sc & SC_START_B, 0);
+        assert_ne!(gb.ifr & IF_SERIAL_B, 0);
+        // nothing connected, ones are shifted in
+        assert_eq!(gb.sb, 0xFF);
+    }
+
+    #[test]
+    fn normal_clock() {
+        // bit 8 falls every 512 cycles
+        transfer(SC_START_B | SC_INTERNAL_CLOCK_B, 8 * 512);
+    }
+
+    #[test]
+    fn fast_clock() {
+        // bit 3 falls every 16 cycles
+        transfer(SC_START_B | SC_FAST_CLOCK_B | SC_INTERNAL_CLOCK_B, 8 * 16);
+    }
+}
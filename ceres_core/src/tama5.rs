@@ -0,0 +1,328 @@
+use {
+    crate::{
+        savestate::{StateError, StateReader, StateWriter},
+        TC_SEC,
+    },
+    alloc::vec::Vec,
+};
+
+/// Size of the RAM of TAMA5 cartridges, saved as their RAM.
+pub(crate) const TAMA5_RAM_SIZE: usize = 0x20;
+/// Size of the clock appended to TAMA5 save files.
+pub(crate) const TAMA5_TRAILER_SIZE: usize = 12;
+
+// registers are selected writing their index to 0xA001 and
+// accessed through 0xA000, a nibble at a time
+const REG_BANK_LO: usize = 0;
+const REG_BANK_HI: usize = 1;
+const REG_WRITE_LO: usize = 4;
+const REG_WRITE_HI: usize = 5;
+const REG_ADDR_HI: usize = 6;
+const REG_ADDR_LO: usize = 7;
+const REG_ACTIVE: usize = 0xA;
+const REG_READ_LO: usize = 0xC;
+const REG_READ_HI: usize = 0xD;
+const WRITABLE_REGS: usize = 8;
+
+// commands in bits 1 to 3 of the high address register
+const CMD_RAM_WRITE: u8 = 0;
+const CMD_RAM_READ: u8 = 1;
+const CMD_CLOCK: u8 = 2;
+
+// clock commands, selected by the address
+const CLOCK_STOP: usize = 0;
+const CLOCK_START: usize = 1;
+const CLOCK_MINUTE_WRITE: usize = 4;
+const CLOCK_HOUR_WRITE: usize = 5;
+const CLOCK_MINUTE_READ: usize = 6;
+const CLOCK_HOUR_READ: usize = 7;
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// The register file of TAMA5 cartridges with their clock.
+/// The ROM bank, RAM and clock are all accessed through
+/// commands written to 0xA000.
+#[derive(Default)]
+pub(crate) struct Tama5 {
+    reg: usize,
+    regs: [u8; WRITABLE_REGS],
+
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    stopped: bool,
+    cycles: u32,
+    // time the save file was written, to catch up with the
+    // time passed since then
+    saved_at: Option<u64>,
+}
+
+impl Tama5 {
+    pub(crate) fn rom_bank(&self) -> usize {
+        usize::from(self.regs[REG_BANK_HI] << 4 | self.regs[REG_BANK_LO])
+    }
+
+    fn address(&self) -> usize {
+        usize::from((self.regs[REG_ADDR_HI] & 1) << 4 | self.regs[REG_ADDR_LO])
+    }
+
+    // the byte returned by reads of the last command
+    fn value(&self, ram: &[u8]) -> u8 {
+        match (self.regs[REG_ADDR_HI] >> 1, self.address()) {
+            (CMD_RAM_READ, addr) => ram[addr],
+            (CMD_CLOCK, CLOCK_MINUTE_READ) => to_bcd(self.minutes),
+            (CMD_CLOCK, CLOCK_HOUR_READ) => to_bcd(self.hours),
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn read(&self, addr: u16, ram: &[u8]) -> u8 {
+        if addr & 1 != 0 {
+            return 0xFF;
+        }
+
+        match self.reg {
+            REG_ACTIVE => 0xF1,
+            REG_READ_LO => self.value(ram) & 0xF | 0xF0,
+            REG_READ_HI => self.value(ram) >> 4 | 0xF0,
+            _ => 0xF0,
+        }
+    }
+
+    // Writing the low address runs the command in the high
+    // address with the byte in the write registers. Returns
+    // true if the RAM or the clock were written.
+    pub(crate) fn write(&mut self, addr: u16, val: u8, ram: &mut [u8]) -> bool {
+        if addr & 1 != 0 {
+            self.reg = usize::from(val & 0xF);
+            return false;
+        }
+
+        if self.reg >= WRITABLE_REGS {
+            return false;
+        }
+        self.regs[self.reg] = val & 0xF;
+
+        if self.reg != REG_ADDR_LO {
+            return false;
+        }
+
+        let out = self.regs[REG_WRITE_HI] << 4 | self.regs[REG_WRITE_LO];
+        match self.regs[REG_ADDR_HI] >> 1 {
+            CMD_RAM_WRITE => {
+                ram[self.address()] = out;
+                true
+            }
+            CMD_CLOCK => self.clock_command(out),
+            _ => false,
+        }
+    }
+
+    fn clock_command(&mut self, out: u8) -> bool {
+        match self.address() {
+            CLOCK_STOP => self.stopped = true,
+            CLOCK_START => self.stopped = false,
+            CLOCK_MINUTE_WRITE => {
+                self.minutes = from_bcd(out).min(59);
+                self.seconds = 0;
+                self.cycles = 0;
+            }
+            CLOCK_HOUR_WRITE => self.hours = from_bcd(out).min(23),
+            _ => return false,
+        }
+        true
+    }
+
+    pub(crate) fn run(&mut self, cycles: u32) {
+        if self.stopped {
+            return;
+        }
+
+        self.cycles += cycles;
+        while self.cycles >= TC_SEC {
+            self.cycles -= TC_SEC;
+            self.advance(1);
+        }
+    }
+
+    // the clock only counts the time of the day
+    fn advance(&mut self, seconds: u64) {
+        let time = u64::from(self.hours) * 3600 + u64::from(self.minutes) * 60;
+        let time = (time + u64::from(self.seconds) + seconds) % SECONDS_PER_DAY;
+
+        // all of them fit in a day
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            self.hours = (time / 3600) as u8;
+            self.minutes = (time / 60 % 60) as u8;
+            self.seconds = (time % 60) as u8;
+        }
+    }
+
+    pub(crate) fn catch_up(&mut self, unix_time: u64) {
+        if let Some(saved_at) = self.saved_at.take() {
+            if !self.stopped {
+                self.advance(unix_time.saturating_sub(saved_at));
+            }
+        }
+    }
+
+    // a little endian timestamp followed by the seconds,
+    // minutes, hours and if the clock is stopped
+    pub(crate) fn read_trailer(&mut self, trailer: &[u8]) {
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&trailer[..8]);
+        self.saved_at = Some(u64::from_le_bytes(timestamp));
+
+        self.seconds = trailer[8].min(59);
+        self.minutes = trailer[9].min(59);
+        self.hours = trailer[10].min(23);
+        self.stopped = trailer[11] & 1 != 0;
+    }
+
+    pub(crate) fn write_trailer(&self, unix_time: u64) -> Vec<u8> {
+        let mut trailer = Vec::with_capacity(TAMA5_TRAILER_SIZE);
+        trailer.extend_from_slice(&unix_time.to_le_bytes());
+        trailer.extend_from_slice(&[
+            self.seconds,
+            self.minutes,
+            self.hours,
+            u8::from(self.stopped),
+        ]);
+        trailer
+    }
+
+    // the register index is at most 0xF
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn write_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.reg as u8);
+        w.write_bytes(&self.regs);
+        w.write_u8(self.seconds);
+        w.write_u8(self.minutes);
+        w.write_u8(self.hours);
+        w.write_bool(self.stopped);
+        w.write_u32(self.cycles);
+    }
+
+    pub(crate) fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.reg = usize::from(r.read_u8()? & 0xF);
+        r.read_bytes(&mut self.regs)?;
+        for reg in &mut self.regs {
+            *reg &= 0xF;
+        }
+        self.seconds = r.read_u8()?.min(59);
+        self.minutes = r.read_u8()?.min(59);
+        self.hours = r.read_u8()?.min(23);
+        self.stopped = r.read_bool()?;
+        self.cycles = r.read_u32()?.min(TC_SEC - 1);
+        Ok(())
+    }
+}
+
+fn to_bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+fn from_bcd(val: u8) -> u8 {
+    (val >> 4) * 10 + (val & 0xF)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            Tama5, CLOCK_HOUR_READ, CLOCK_HOUR_WRITE, CLOCK_MINUTE_READ, CLOCK_MINUTE_WRITE,
+            CLOCK_START, CLOCK_STOP, CMD_CLOCK, CMD_RAM_READ, CMD_RAM_WRITE, REG_ADDR_HI,
+            REG_ADDR_LO, REG_READ_HI, REG_READ_LO, REG_WRITE_HI, REG_WRITE_LO, TAMA5_RAM_SIZE,
+        },
+        crate::TC_SEC,
+    };
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn select(chip: &mut Tama5, ram: &mut [u8], reg: usize) {
+        chip.write(0xA001, reg as u8, ram);
+    }
+
+    fn write_reg(chip: &mut Tama5, ram: &mut [u8], reg: usize, val: u8) -> bool {
+        select(chip, ram, reg);
+        chip.write(0xA000, val, ram)
+    }
+
+    // returns true if the RAM or the clock were written
+    #[allow(clippy::cast_possible_truncation)]
+    fn command(chip: &mut Tama5, ram: &mut [u8], cmd: u8, addr: usize, out: u8) -> bool {
+        write_reg(chip, ram, REG_WRITE_LO, out & 0xF);
+        write_reg(chip, ram, REG_WRITE_HI, out >> 4);
+        write_reg(chip, ram, REG_ADDR_HI, cmd << 1 | (addr >> 4) as u8);
+        write_reg(chip, ram, REG_ADDR_LO, addr as u8 & 0xF)
+    }
+
+    fn read_value(chip: &mut Tama5, ram: &mut [u8]) -> u8 {
+        select(chip, ram, REG_READ_LO);
+        let lo = chip.read(0xA000, ram);
+        select(chip, ram, REG_READ_HI);
+        let hi = chip.read(0xA000, ram);
+        assert_eq!(lo & 0xF0, 0xF0);
+        assert_eq!(hi & 0xF0, 0xF0);
+        (hi & 0xF) << 4 | lo & 0xF
+    }
+
+    #[test]
+    fn ram_write_and_read() {
+        let mut chip = Tama5::default();
+        let mut ram = [0; TAMA5_RAM_SIZE];
+
+        assert!(command(&mut chip, &mut ram, CMD_RAM_WRITE, 0x13, 0xA5));
+        assert!(command(&mut chip, &mut ram, CMD_RAM_WRITE, 0x02, 0x3C));
+        assert_eq!(ram[0x13], 0xA5);
+        assert_eq!(ram[0x02], 0x3C);
+
+        assert!(!command(&mut chip, &mut ram, CMD_RAM_READ, 0x13, 0));
+        assert_eq!(read_value(&mut chip, &mut ram), 0xA5);
+        assert!(!command(&mut chip, &mut ram, CMD_RAM_READ, 0x02, 0));
+        assert_eq!(read_value(&mut chip, &mut ram), 0x3C);
+    }
+
+    // hours and minutes in BCD
+    fn read_clock(chip: &mut Tama5, ram: &mut [u8]) -> (u8, u8) {
+        command(chip, ram, CMD_CLOCK, CLOCK_HOUR_READ, 0);
+        let hours = read_value(chip, ram);
+        command(chip, ram, CMD_CLOCK, CLOCK_MINUTE_READ, 0);
+        (hours, read_value(chip, ram))
+    }
+
+    #[test]
+    fn clock_commands() {
+        let mut chip = Tama5::default();
+        let mut ram = [0; TAMA5_RAM_SIZE];
+
+        // in BCD
+        assert!(command(
+            &mut chip,
+            &mut ram,
+            CMD_CLOCK,
+            CLOCK_HOUR_WRITE,
+            0x23
+        ));
+        assert!(command(
+            &mut chip,
+            &mut ram,
+            CMD_CLOCK,
+            CLOCK_MINUTE_WRITE,
+            0x59
+        ));
+        assert_eq!(read_clock(&mut chip, &mut ram), (0x23, 0x59));
+
+        // wraps at midnight
+        chip.run(TC_SEC * 60);
+        assert_eq!(read_clock(&mut chip, &mut ram), (0x00, 0x00));
+
+        assert!(command(&mut chip, &mut ram, CMD_CLOCK, CLOCK_STOP, 0));
+        chip.run(TC_SEC * 120);
+        assert_eq!(read_clock(&mut chip, &mut ram), (0x00, 0x00));
+
+        assert!(command(&mut chip, &mut ram, CMD_CLOCK, CLOCK_START, 0));
+        chip.run(TC_SEC * 120);
+        assert_eq!(read_clock(&mut chip, &mut ram), (0x00, 0x02));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::Gb;
+
+// mooneye tests run LD B,B when done, with the Fibonacci
+// numbers in the registers on success
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+// blargg tests print the result through the serial port
+const BLARGG_PASSED: &[u8; 6] = b"Passed";
+const BLARGG_FAILED: &[u8; 6] = b"Failed";
+
+/// Result reported by a mooneye or blargg test ROM.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TestResult {
+    Passed,
+    Failed,
+}
+
+impl Gb {
+    /// Returns the result reported by the running test ROM,
+    /// `None` if it hasn't finished or isn't a mooneye or
+    /// blargg test.
+    #[must_use]
+    pub fn test_result(&self) -> Option<TestResult> {
+        if self.test_passed {
+            Some(TestResult::Passed)
+        } else if self.test_failed {
+            Some(TestResult::Failed)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn check_mooneye_result(&mut self) {
+        let [bc, de, hl] = [self.bc, self.de, self.hl].map(u16::to_be_bytes);
+        let regs = [bc[0], bc[1], de[0], de[1], hl[0], hl[1]];
+
+        self.test_passed |= regs == MOONEYE_PASSED;
+        self.test_failed |= regs == MOONEYE_FAILED;
+    }
+
+    pub(crate) fn check_blargg_result(&mut self, byte: u8) {
+        self.serial_tail.copy_within(1.., 0);
+        self.serial_tail[5] = byte;
+
+        self.test_passed |= &self.serial_tail == BLARGG_PASSED;
+        self.test_failed |= &self.serial_tail == BLARGG_FAILED;
+    }
+}
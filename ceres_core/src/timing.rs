@@ -4,6 +4,7 @@ impl Gb {
     pub(crate) fn advance_cycles(&mut self, mut cycles: i32) {
         // affected by speed boost
         self.run_timers(cycles);
+        self.poll_serial_device();
         self.dma_cycles += cycles;
 
         // not affected by speed boost
@@ -11,6 +12,9 @@ impl Gb {
             cycles >>= 1;
         }
 
+        self.elapsed_cycles += u64::from(cycles.unsigned_abs());
+        self.cart.run(cycles.unsigned_abs());
+
         // TODO: is this order right?
         self.run_ppu(cycles);
         self.run_dma();
@@ -44,6 +48,8 @@ impl Gb {
 
     pub(crate) fn run_timers(&mut self, cycles: i32) {
         for _ in 0..cycles {
+            let old_system_clk = self.system_clk;
+
             // Falling edge detector
             let old_bit = self.tac_enable && self.sys_clk_tac_mux();
             self.system_clk = self.system_clk.wrapping_add(1);
@@ -53,6 +59,8 @@ impl Gb {
             if old_bit && !new_bit {
                 self.inc_tima();
             }
+
+            self.run_serial(old_system_clk);
         }
     }
 
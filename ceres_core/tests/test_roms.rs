@@ -0,0 +1,156 @@
+//! Runs the mooneye and blargg test ROMs listed in
+//! `manifest.txt`, inside the directory in the
+//! `CERES_TEST_ROMS` environment variable or `tests/roms`.
+//! ROMs aren't distributed with the emulator, the test is
+//! skipped when the manifest is missing.
+//!
+//! Every manifest line has the form
+//! `<rom path> <passed|failed> [dmg|mgb|cgb]`, `#` starts a
+//! comment. Running with `--release` is recommended.
+//!
+//! The mooneye MBC2 tests are also run when the directory
+//! has the `emulator-only/mbc2` directory of the mooneye
+//! test suite, whatever the manifest lists.
+
+use {
+    ceres_core::{Cartridge, CartridgeHeader, Gb, Model, TestResult},
+    std::{
+        env, fs,
+        path::{Path, PathBuf},
+    },
+};
+
+// two minutes, enough for the slowest blargg test
+const MAX_FRAMES: u32 = 60 * 60 * 2;
+
+const MOONEYE_MBC2: [&str; 7] = [
+    "bits_ramg.gb",
+    "bits_romb.gb",
+    "bits_unused.gb",
+    "ram.gb",
+    "rom_1Mb.gb",
+    "rom_2Mb.gb",
+    "rom_512kb.gb",
+];
+
+struct Entry {
+    path: PathBuf,
+    expected: TestResult,
+    model: Option<Model>,
+}
+
+fn parse_manifest(dir: &Path, text: &str) -> Vec<Entry> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let path = words.next()?;
+
+            let expected = match words.next() {
+                Some("passed") => TestResult::Passed,
+                Some("failed") => TestResult::Failed,
+                _ => panic!("manifest line {}: expected passed or failed", i + 1),
+            };
+
+            let model = match words.next() {
+                None => None,
+                Some("dmg") => Some(Model::Dmg),
+                Some("mgb") => Some(Model::Mgb),
+                Some("cgb") => Some(Model::Cgb),
+                Some(m) => panic!("manifest line {}: unknown model {m}", i + 1),
+            };
+
+            Some(Entry {
+                path: dir.join(path),
+                expected,
+                model,
+            })
+        })
+        .collect()
+}
+
+fn run(entry: &Entry) -> Result<Option<TestResult>, String> {
+    let rom = fs::read(&entry.path).map_err(|e| e.to_string())?;
+    let cart = Cartridge::new(&rom, None).map_err(|e| format!("{e:?}"))?;
+    let model = entry.model.unwrap_or_else(|| {
+        CartridgeHeader::new(&rom).map_or(Model::Cgb, |h| Model::from_header(&h))
+    });
+
+    let mut gb = Gb::new(model, cart, |_, _| (), 48000);
+
+    for _ in 0..MAX_FRAMES {
+        gb.run_frame();
+
+        if let Some(result) = gb.test_result() {
+            return Ok(Some(result));
+        }
+    }
+
+    Ok(None)
+}
+
+fn rom_dir() -> PathBuf {
+    env::var_os("CERES_TEST_ROMS").map_or_else(
+        || PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"),
+        PathBuf::from,
+    )
+}
+
+fn check(entries: &[Entry]) {
+    let mut failures = Vec::new();
+
+    for entry in entries {
+        match run(entry) {
+            Ok(Some(result)) if result == entry.expected => (),
+            Ok(result) => failures.push(format!(
+                "{}: expected {:?}, got {:?}",
+                entry.path.display(),
+                entry.expected,
+                result
+            )),
+            Err(e) => failures.push(format!("{}: {e}", entry.path.display())),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} test ROMs failed:\n{}",
+        failures.len(),
+        entries.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_roms() {
+    let dir = rom_dir();
+
+    let Ok(manifest) = fs::read_to_string(dir.join("manifest.txt")) else {
+        eprintln!("no manifest in {}, skipping test ROMs", dir.display());
+        return;
+    };
+
+    check(&parse_manifest(&dir, &manifest));
+}
+
+#[test]
+fn mooneye_mbc2() {
+    let dir = rom_dir().join("emulator-only/mbc2");
+
+    if !dir.is_dir() {
+        eprintln!("no {}, skipping MBC2 test ROMs", dir.display());
+        return;
+    }
+
+    let entries: Vec<Entry> = MOONEYE_MBC2
+        .iter()
+        .map(|rom| Entry {
+            path: dir.join(rom),
+            expected: TestResult::Passed,
+            model: None,
+        })
+        .collect();
+
+    check(&entries);
+}
@@ -1,87 +1,192 @@
 use {
-    crate::{audio, video},
-    ceres_core::{Gb, Model},
-    glutin::event_loop::EventLoop,
-    std::{
-        fs::File,
-        io::Read,
-        path::{Path, PathBuf},
+    crate::{
+        audio,
+        netlink::NetLink,
+        printer::PrinterOutput,
+        save::{self, SaveFile},
+        video,
     },
+    ceres_core::{Cartridge, CartridgeHeader, Gb, LinkCable, Model, Sample, StillImage},
+    glutin::event_loop::EventLoop,
+    std::path::{Path, PathBuf},
 };
 
 /// # Panics
 ///
-/// Will panic on invalid rom or ram file
-pub fn run(model: Model, mut rom_path: PathBuf) -> ! {
-    fn read_file_into(path: &Path, buf: &mut [u8]) -> Result<(), std::io::Error> {
-        let mut f = File::open(path)?;
-        let _ = f.read(buf).unwrap();
-        Ok(())
+/// Will panic on invalid rom file
+pub fn run(
+    model: Option<Model>,
+    rom_path: &Path,
+    save_dir: Option<&Path>,
+    link_rom_path: Option<PathBuf>,
+    net: Option<NetLink>,
+    printer: Option<PrinterOutput>,
+    camera: Option<StillImage>,
+) -> ! {
+    let save = SaveFile::new(rom_path, save_dir);
+    let (mut gb, header) = load(model, rom_path, &save, imp::apu_frame_callback);
+
+    if let Some(net) = &net {
+        net.connect(&mut gb);
     }
 
-    read_file_into(&rom_path, Gb::cartridge_rom_mut()).unwrap();
+    if let Some(printer) = &printer {
+        printer.connect(&mut gb);
+    }
 
-    let sav_path = {
-        rom_path.set_extension("sav");
-        rom_path
-    };
+    if let Some(camera) = camera {
+        gb.set_camera_source(Box::new(camera));
+    }
 
-    read_file_into(&sav_path, Gb::cartridge_ram_mut()).ok();
+    // only the first GameBoy is heard
+    let link = link_rom_path.map(|link_rom_path| {
+        let mut link_save = SaveFile::new(&link_rom_path, save_dir);
+        if link_save.path() == save.path() {
+            link_save.set_extension("2.sav");
+        }
 
-    let audio = audio::Renderer::init();
+        let (mut link_gb, _) = load(model, &link_rom_path, &link_save, |_, _| ());
+        let cable = LinkCable::new(&mut gb, &mut link_gb);
 
-    let gb = Gb::new(
-        model,
-        imp::apu_frame_callback,
-        audio::Renderer::sample_rate(),
-    )
-    .unwrap();
+        imp::Link {
+            gb: link_gb,
+            cable,
+            save: link_save,
+        }
+    });
 
     let event_loop = EventLoop::new();
-    let video = video::Renderer::init(&event_loop);
+    let title = match header {
+        Some(header) => format!(
+            "{} - {} ({:?})",
+            super::CERES_STR,
+            header.title(),
+            gb.model()
+        ),
+        None => format!("{} ({:?})", super::CERES_STR, gb.model()),
+    };
+    let screens = if link.is_some() { 2 } else { 1 };
+    let video = video::Renderer::init(&event_loop, &title, screens);
+    let audio = audio::Renderer::init();
 
-    let mut emu = imp::Emu::new(gb, video, audio, sav_path);
+    let mut emu = imp::Emu::new(gb, link, net, printer, video, audio, save);
 
     event_loop.run(move |event, _, control_flow| emu.main_loop(event, control_flow));
 }
 
+fn load(
+    model: Option<Model>,
+    rom_path: &Path,
+    save: &SaveFile,
+    apu_frame_callback: fn(Sample, Sample),
+) -> (Box<Gb>, Option<CartridgeHeader>) {
+    let rom = std::fs::read(rom_path).unwrap();
+    let header = CartridgeHeader::new(&rom).ok();
+
+    // a CGB runs almost everything, use it when the header
+    // can't be read
+    let model = model.unwrap_or_else(|| header.as_ref().map_or(Model::Cgb, Model::from_header));
+
+    // a save that can't be loaded is kept aside, the game
+    // starts with blank RAM
+    let ram = save.read();
+    let cart = Cartridge::new(&rom, ram.as_deref()).unwrap_or_else(|e| {
+        eprintln!("couldn't load save file {}: {e:?}", save.path().display());
+        save.back_up();
+        Cartridge::new(&rom, None).unwrap()
+    });
+
+    let mut gb = Gb::new(
+        model,
+        cart,
+        apu_frame_callback,
+        audio::Renderer::sample_rate(),
+    );
+    gb.catch_up_cartridge_rtc(save::unix_time());
+
+    (gb, header)
+}
+
 mod imp {
     use {
-        crate::{audio, video},
-        ceres_core::{Gb, Sample},
+        crate::{audio, netlink::NetLink, printer::PrinterOutput, save::SaveFile, video},
+        ceres_core::{Button, Gb, LinkCable, Rewind, Sample, PX_HEIGHT, PX_WIDTH},
         glutin::{
+            dpi::PhysicalPosition,
             event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
             event_loop::ControlFlow,
         },
-        std::{fs::File, io::Write, path::PathBuf, ptr::null_mut},
+        std::{path::PathBuf, ptr::null_mut},
     };
 
     static mut EMU: *mut Emu = null_mut();
 
+    // a snapshot every 2 frames, about 10 seconds of history
+    const REWIND_INTERVAL: u32 = 2;
+    const REWIND_CAPACITY: usize = 300;
+
+    // pixels the screen moves while rumbling
+    const RUMBLE_SHAKE: usize = 2;
+
+    // unsaved progress is written every 5 seconds
+    const AUTOSAVE_FRAMES: u32 = 300;
+
+    /// A second ``GameBoy`` connected through a link cable.
+    pub struct Link {
+        pub gb: Box<Gb>,
+        pub cable: LinkCable,
+        pub save: SaveFile,
+    }
+
     pub struct Emu {
-        gb: &'static mut Gb,
+        gb: Box<Gb>,
+        link: Option<Link>,
+        // both screens side by side in link mode
+        link_frame: Vec<u8>,
+        net: Option<NetLink>,
+        printer: Option<PrinterOutput>,
         video: video::Renderer,
         audio: audio::Renderer,
+        rumble_frame: Vec<u8>,
+        // pixels the screen is moved this frame
+        shake: usize,
 
-        sav_path: PathBuf,
+        save: SaveFile,
+        frames_since_save: u32,
         has_focus: bool,
         paused: bool,
+        state_slot: u8,
+        rewind: Rewind,
+        rewinding: bool,
     }
 
     impl Emu {
         pub fn new(
-            gb: &'static mut Gb,
+            gb: Box<Gb>,
+            link: Option<Link>,
+            net: Option<NetLink>,
+            printer: Option<PrinterOutput>,
             video: video::Renderer,
             audio: audio::Renderer,
-            sav_path: PathBuf,
+            save: SaveFile,
         ) -> Self {
             let mut emu = Emu {
                 gb,
-                sav_path,
+                link,
+                link_frame: vec![0; PX_WIDTH as usize * PX_HEIGHT as usize * 4 * 2],
+                net,
+                printer,
+                rumble_frame: vec![0; PX_WIDTH as usize * PX_HEIGHT as usize * 4],
+                shake: 0,
+                save,
+                frames_since_save: 0,
                 video,
                 has_focus: true,
                 audio,
                 paused: false,
+                state_slot: 1,
+                rewind: Rewind::new(REWIND_INTERVAL, REWIND_CAPACITY),
+                rewinding: false,
             };
 
             unsafe {
@@ -101,6 +206,7 @@ mod imp {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                     WindowEvent::Focused(is_focused) => self.focus(is_focused),
                     WindowEvent::KeyboardInput { input, .. } => self.key_input(input),
+                    WindowEvent::CursorMoved { position, .. } => self.tilt(position),
                     _ => (),
                 },
                 Event::MainEventsCleared => self.main_cleared(control_flow),
@@ -118,19 +224,83 @@ mod imp {
                 return;
             }
 
-            self.gb.run_frame();
+            self.frames_since_save += 1;
+            if self.frames_since_save >= AUTOSAVE_FRAMES {
+                self.frames_since_save = 0;
+                self.save();
+            }
+
+            if let Some(link) = &mut self.link {
+                link.cable.run_frame(&mut self.gb, &mut link.gb);
+
+                let row = PX_WIDTH as usize * 4;
+                let screens = self
+                    .gb
+                    .pixel_data()
+                    .chunks(row)
+                    .zip(link.gb.pixel_data().chunks(row));
+                for (dst, (a, b)) in self.link_frame.chunks_mut(row * 2).zip(screens) {
+                    dst[..row].copy_from_slice(a);
+                    dst[row..].copy_from_slice(b);
+                }
+
+                self.video.draw_frame(&self.link_frame);
+                return;
+            }
+
+            if self.rewinding {
+                if !self.rewind.rewind(&mut self.gb) {
+                    return;
+                }
+                // the restored frame has to be emulated to be
+                // displayed, it gets discarded by the next rewind
+                self.gb.run_frame();
+            } else {
+                self.gb.run_frame();
+                self.rewind.push(&self.gb);
+            }
+
+            if let Some(net) = &mut self.net {
+                net.sync(&self.gb);
+            }
+
+            if let Some(printer) = &mut self.printer {
+                printer.save_printouts();
+            }
+
             let rgba = self.gb.pixel_data();
-            self.video.draw_frame(rgba);
+
+            // the screen shakes while the rumble motor is on
+            if self.gb.cartridge_rumble() {
+                self.shake = RUMBLE_SHAKE - self.shake;
+
+                self.rumble_frame.copy_from_slice(rgba);
+                for row in self.rumble_frame.chunks_mut(PX_WIDTH as usize * 4) {
+                    row.rotate_right(self.shake * 4);
+                }
+
+                self.video.draw_frame(&self.rumble_frame);
+            } else {
+                self.video.draw_frame(rgba);
+            }
         }
 
         pub fn key_input(&mut self, input: KeyboardInput) {
-            use ceres_core::Button;
-
             if !self.has_focus {
                 return;
             }
 
             if let Some(key) = input.virtual_keycode {
+                if let Some(button) = player2_button(key) {
+                    if let Some(link) = &mut self.link {
+                        match input.state {
+                            ElementState::Pressed => link.gb.press(button),
+                            ElementState::Released => link.gb.release(button),
+                        }
+                    }
+                    return;
+                }
+
                 match input.state {
                     ElementState::Pressed => match key {
                         VirtualKeyCode::W => self.gb.press(Button::Up),
@@ -144,6 +314,13 @@ mod imp {
                         // System
                         VirtualKeyCode::F => self.video.toggle_fullscreen(),
                         VirtualKeyCode::Space => self.toggle_pause(),
+                        VirtualKeyCode::F5 => self.save_state(),
+                        VirtualKeyCode::F8 => self.load_state(),
+                        VirtualKeyCode::Key1 => self.state_slot = 1,
+                        VirtualKeyCode::Key2 => self.state_slot = 2,
+                        VirtualKeyCode::Key3 => self.state_slot = 3,
+                        VirtualKeyCode::Key4 => self.state_slot = 4,
+                        VirtualKeyCode::R => self.set_rewinding(true),
                         _ => (),
                     },
                     ElementState::Released => match key {
@@ -155,20 +332,93 @@ mod imp {
                         VirtualKeyCode::L => self.gb.release(Button::B),
                         VirtualKeyCode::Return => self.gb.release(Button::Start),
                         VirtualKeyCode::Back => self.gb.release(Button::Select),
+                        VirtualKeyCode::R => self.set_rewinding(false),
                         _ => (),
                     },
                 }
             }
         }
 
+        // The mouse tilts MBC7 cartridges, the center of the
+        // window is level and the edges are tilted 1 g.
+        fn tilt(&mut self, position: PhysicalPosition<f64>) {
+            let size = self.video.window_size();
+            let axis = |pos: f64, len: u32| {
+                let tilt = (pos / f64::from(len.max(1))).mul_add(2.0, -1.0);
+                (tilt.clamp(-1.0, 1.0) * 1000.0) as i32
+            };
+
+            self.gb
+                .set_accelerometer(axis(position.x, size.width), axis(position.y, size.height));
+        }
+
         pub fn focus(&mut self, is_focused: bool) {
             self.has_focus = is_focused;
         }
 
         pub fn save(&mut self) {
-            if Gb::cartridge_has_battery() {
-                let mut f = File::create(&self.sav_path).unwrap();
-                f.write_all(Gb::cartridge_ram()).unwrap();
+            self.save.write_if_dirty(&mut self.gb);
+
+            if let Some(link) = &mut self.link {
+                link.save.write_if_dirty(&mut link.gb);
+            }
+        }
+
+        fn state_path(&self) -> PathBuf {
+            self.save
+                .path()
+                .with_extension(format!("ss{}", self.state_slot))
+        }
+
+        pub fn save_state(&mut self) {
+            // a state of only one side would break the link
+            if self.link.is_some() || self.net.is_some() {
+                return;
+            }
+
+            let mut buf = vec![0; self.gb.save_state_size()];
+
+            match self.gb.save_state(&mut buf) {
+                Ok(_) => {
+                    if let Err(e) = std::fs::write(self.state_path(), buf) {
+                        eprintln!("couldn't write save state: {e}");
+                    }
+                }
+                Err(e) => eprintln!("couldn't save state: {e:?}"),
+            }
+        }
+
+        pub fn load_state(&mut self) {
+            if self.link.is_some() || self.net.is_some() {
+                return;
+            }
+
+            match std::fs::read(self.state_path()) {
+                Ok(buf) => {
+                    if let Err(e) = self.gb.load_state(&buf) {
+                        eprintln!("couldn't load state: {e:?}");
+                    } else {
+                        self.rewind.clear();
+                    }
+                }
+                Err(e) => eprintln!("couldn't read save state: {e}"),
+            }
+        }
+
+        fn set_rewinding(&mut self, rewinding: bool) {
+            if self.rewinding == rewinding || self.link.is_some() || self.net.is_some() {
+                return;
+            }
+
+            self.rewinding = rewinding;
+
+            // rewound audio would only be noise
+            if !self.paused {
+                if rewinding {
+                    self.audio.pause();
+                } else {
+                    self.audio.play();
+                }
             }
         }
 
@@ -183,6 +433,20 @@ mod imp {
         }
     }
 
+    fn player2_button(key: VirtualKeyCode) -> Option<Button> {
+        match key {
+            VirtualKeyCode::Up => Some(Button::Up),
+            VirtualKeyCode::Left => Some(Button::Left),
+            VirtualKeyCode::Down => Some(Button::Down),
+            VirtualKeyCode::Right => Some(Button::Right),
+            VirtualKeyCode::Period => Some(Button::A),
+            VirtualKeyCode::Comma => Some(Button::B),
+            VirtualKeyCode::RShift => Some(Button::Start),
+            VirtualKeyCode::RControl => Some(Button::Select),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn apu_frame_callback(l: Sample, r: Sample) {
         let emu = unsafe { &mut *EMU };
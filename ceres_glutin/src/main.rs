@@ -43,11 +43,14 @@
 use {
     ceres_core::Model,
     clap::{ArgEnum, Parser},
-    std::path::PathBuf,
+    std::{net::IpAddr, path::PathBuf},
 };
 
 mod audio;
 mod emu;
+mod netlink;
+mod printer;
+mod save;
 mod video;
 
 const CERES_STR: &str = "Ceres";
@@ -57,12 +60,49 @@ const CERES_STR: &str = "Ceres";
 struct Cli {
     rom_path: String,
 
-    #[clap(short, long, arg_enum)]
-    model: Option<CliModel>,
+    #[clap(short, long, arg_enum, default_value = "auto")]
+    model: CliModel,
+
+    /// Directory for save files and save states, by default
+    /// they are next to the ROM
+    #[clap(long)]
+    save_dir: Option<PathBuf>,
+
+    /// Connect a second emulator running this ROM through a
+    /// link cable, shown side by side
+    #[clap(long, conflicts_with_all = &["host", "join"])]
+    link: Option<String>,
+
+    /// Wait for another Ceres to join through a link cable
+    /// over TCP on this port
+    #[clap(long, conflicts_with = "join")]
+    host: Option<u16>,
+
+    /// Address to wait on with --host, the default only
+    /// accepts connections from this machine and 0.0.0.0
+    /// accepts them from any network
+    #[clap(long, requires = "host", default_value = "127.0.0.1")]
+    bind: IpAddr,
+
+    /// Join another Ceres through a link cable over TCP,
+    /// the address has the form host:port
+    #[clap(long)]
+    join: Option<String>,
+
+    /// Connect a Game Boy Printer, saving printouts as PNG
+    /// files in this directory
+    #[clap(long, conflicts_with_all = &["link", "host", "join"])]
+    printer: Option<PathBuf>,
+
+    /// Make the Game Boy Camera take pictures of this PNG
+    /// image
+    #[clap(long)]
+    camera: Option<PathBuf>,
 }
 
 #[derive(Clone, ArgEnum)]
 enum CliModel {
+    Auto,
     Dmg,
     Mgb,
     Cgb,
@@ -71,15 +111,41 @@ enum CliModel {
 fn main() {
     let cli = Cli::parse();
 
-    let model = cli.model.map_or(Model::Cgb, move |s| match s {
-        CliModel::Dmg => Model::Dmg,
-        CliModel::Mgb => Model::Mgb,
-        CliModel::Cgb => Model::Cgb,
-    });
+    // None chooses the model from the ROM header
+    let model = match cli.model {
+        CliModel::Auto => None,
+        CliModel::Dmg => Some(Model::Dmg),
+        CliModel::Mgb => Some(Model::Mgb),
+        CliModel::Cgb => Some(Model::Cgb),
+    };
 
     let rom_path = Some(PathBuf::from(cli.rom_path));
 
     if let Some(rom_path) = rom_path {
-        emu::run(model, rom_path);
+        let net = if let Some(port) = cli.host {
+            println!("waiting for the other side to join on {}:{port}", cli.bind);
+            Some(netlink::NetLink::host((cli.bind, port)).expect("couldn't host link cable"))
+        } else {
+            cli.join
+                .map(|addr| netlink::NetLink::join(addr).expect("couldn't join link cable"))
+        };
+
+        let printer = cli
+            .printer
+            .map(|dir| printer::PrinterOutput::new(dir, &rom_path));
+
+        let camera = cli
+            .camera
+            .map(|path| ceres_png::read_still_image(&path).expect("couldn't read camera image"));
+
+        emu::run(
+            model,
+            &rom_path,
+            cli.save_dir.as_deref(),
+            cli.link.map(PathBuf::from),
+            net,
+            printer,
+            camera,
+        );
     }
 }
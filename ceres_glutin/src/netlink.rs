@@ -0,0 +1,201 @@
+//! Link cable over TCP. Both sides run in lockstep, after
+//! every frame each one sends the byte it transferred as
+//! master and whether it waits for a transfer, then waits
+//! for the same message from the other side. Transfers only
+//! see the state of the other side at the end of the
+//! previous frame, so the result doesn't depend on network
+//! timing, and at most one byte is transferred every frame.
+
+use {
+    ceres_core::{Gb, SerialDevice},
+    std::{
+        cell::RefCell,
+        io::{self, Read, Write},
+        net::{TcpListener, TcpStream, ToSocketAddrs},
+        rc::Rc,
+    },
+};
+
+#[derive(Default)]
+struct State {
+    connected: bool,
+    // SB of the other side if it was waiting for a transfer
+    // at the end of the last frame
+    peer_waiting: Option<u8>,
+    // byte sent as master during this frame
+    sent: Option<u8>,
+    // byte sent by the other side as master, not received
+    // yet
+    received: Option<u8>,
+}
+
+struct NetPort {
+    state: Rc<RefCell<State>>,
+}
+
+impl SerialDevice for NetPort {
+    fn exchange(&mut self, sent: u8) -> u8 {
+        let mut state = self.state.borrow_mut();
+
+        match state.peer_waiting.take() {
+            Some(sb) => {
+                state.sent = Some(sent);
+                sb
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll_external(&mut self, _: u8) -> Option<u8> {
+        self.state.borrow_mut().received.take()
+    }
+}
+
+pub struct NetLink {
+    stream: TcpStream,
+    state: Rc<RefCell<State>>,
+}
+
+impl NetLink {
+    /// Waits for the other side to join on `addr`. Only
+    /// local connections are accepted if it's a loopback
+    /// address.
+    pub fn host(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn join(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        // a message is sent every frame, don't wait to fill
+        // packets
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream,
+            state: Rc::new(RefCell::new(State {
+                connected: true,
+                ..State::default()
+            })),
+        })
+    }
+
+    /// Connects the serial port of `gb` to the cable.
+    pub fn connect(&self, gb: &mut Gb) {
+        gb.connect_serial(Box::new(NetPort {
+            state: Rc::clone(&self.state),
+        }));
+    }
+
+    /// Exchanges the state of both sides, should be called
+    /// after every frame. Once disconnected transfers shift
+    /// in ones, as with no cable.
+    pub fn sync(&mut self, gb: &Gb) {
+        if !self.state.borrow().connected {
+            return;
+        }
+
+        if let Err(e) = self.exchange_frame(gb) {
+            eprintln!("link cable disconnected: {e}");
+
+            let mut state = self.state.borrow_mut();
+            state.connected = false;
+            state.peer_waiting = None;
+            state.received = None;
+        }
+    }
+
+    // message: waiting flag, SB, sent flag and byte sent as
+    // master
+    fn exchange_frame(&mut self, gb: &Gb) -> io::Result<()> {
+        let waiting = gb.waiting_serial_byte();
+        let sent = self.state.borrow_mut().sent.take();
+
+        self.stream.write_all(&[
+            u8::from(waiting.is_some()),
+            waiting.unwrap_or(0xFF),
+            u8::from(sent.is_some()),
+            sent.unwrap_or(0xFF),
+        ])?;
+
+        let mut msg = [0; 4];
+        self.stream.read_exact(&mut msg)?;
+
+        let mut state = self.state.borrow_mut();
+        state.peer_waiting = (msg[0] != 0).then_some(msg[1]);
+        if msg[2] != 0 {
+            state.received = Some(msg[3]);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{NetLink, NetPort},
+        ceres_core::{Cartridge, Gb, Model, SerialDevice},
+        std::{net::TcpListener, rc::Rc, thread},
+    };
+
+    fn gb(code: &[u8]) -> Box<Gb> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+        let cart = Cartridge::new(&rom, None).unwrap();
+        Gb::new(Model::Dmg, cart, |_, _| (), 48000)
+    }
+
+    #[test]
+    fn loopback_exchange_and_disconnect() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .port();
+
+        let joiner = thread::spawn(move || {
+            // ld a, 0x42; ldh (SB), a; ld a, 0x80; ldh (SC), a;
+            // jr -2
+            let mut gb = gb(&[0x3E, 0x42, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE]);
+
+            let mut link = loop {
+                if let Ok(link) = NetLink::join(("127.0.0.1", port)) {
+                    break link;
+                }
+                thread::yield_now();
+            };
+            link.connect(&mut gb);
+
+            while gb.waiting_serial_byte().is_none() {
+                gb.run_frame();
+            }
+
+            link.sync(&gb);
+            link.sync(&gb);
+            assert_eq!(link.state.borrow().received, Some(0x13));
+        });
+
+        // jr -2
+        let mut gb = gb(&[0x18, 0xFE]);
+        let mut link = NetLink::host(("127.0.0.1", port)).unwrap();
+        link.connect(&mut gb);
+        let mut device = NetPort {
+            state: Rc::clone(&link.state),
+        };
+
+        link.sync(&gb);
+        assert_eq!(device.exchange(0x13), 0x42);
+        link.sync(&gb);
+
+        joiner.join().unwrap();
+
+        link.sync(&gb);
+        assert!(!link.state.borrow().connected);
+        assert_eq!(device.exchange(0x13), 0xFF);
+        assert_eq!(device.poll_external(0x13), None);
+    }
+}
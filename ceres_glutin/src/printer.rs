@@ -0,0 +1,57 @@
+use {
+    ceres_core::{Gb, Printer},
+    ceres_png::write_printout,
+    std::path::{Path, PathBuf},
+};
+
+/// Saves everything printed by a Game Boy Printer as PNG
+/// files in a directory.
+pub struct PrinterOutput {
+    printer: Printer,
+    dir: PathBuf,
+    prefix: String,
+    count: u32,
+}
+
+impl PrinterOutput {
+    /// Printouts are named after the ROM file, like
+    /// `game-print-1.png`.
+    pub fn new(dir: PathBuf, rom_path: &Path) -> Self {
+        let prefix = rom_path
+            .file_stem()
+            .map_or_else(|| "ceres".to_owned(), |s| s.to_string_lossy().into_owned());
+
+        Self {
+            printer: Printer::new(),
+            dir,
+            prefix,
+            count: 0,
+        }
+    }
+
+    pub fn connect(&self, gb: &mut Gb) {
+        self.printer.connect(gb);
+    }
+
+    /// Writes the printouts finished since the last call,
+    /// should be called after every frame.
+    pub fn save_printouts(&mut self) {
+        while let Some(printout) = self.printer.take_printout() {
+            // don't overwrite printouts of previous runs
+            let path = loop {
+                self.count += 1;
+                let path = self
+                    .dir
+                    .join(format!("{}-print-{}.png", self.prefix, self.count));
+                if !path.exists() {
+                    break path;
+                }
+            };
+
+            match write_printout(&path, &printout) {
+                Ok(()) => println!("printed {}", path.display()),
+                Err(e) => eprintln!("couldn't write printout: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use {
+    ceres_core::Gb,
+    std::{
+        fs::{self, File},
+        io::{self, Write},
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// The save file of a cartridge with a battery, with the
+/// contents of its RAM followed by the clock of cartridges
+/// with one, in the format used by VBA and BGB.
+pub struct SaveFile {
+    path: PathBuf,
+}
+
+impl SaveFile {
+    /// The save file of `rom_path`, next to it or in
+    /// `save_dir`.
+    pub fn new(rom_path: &Path, save_dir: Option<&Path>) -> Self {
+        let path = rom_path.with_extension("sav");
+        let path = match (save_dir, path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => path,
+        };
+
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Used to tell apart two saves of the same ROM.
+    pub fn set_extension(&mut self, extension: &str) {
+        self.path.set_extension(extension);
+    }
+
+    pub fn read(&self) -> Option<Vec<u8>> {
+        fs::read(&self.path).ok()
+    }
+
+    /// Copies the save to `<save>.bak`, so it isn't
+    /// overwritten by the next write.
+    pub fn back_up(&self) {
+        let mut backup = self.path.clone().into_os_string();
+        backup.push(".bak");
+
+        match fs::copy(&self.path, &backup) {
+            Ok(_) => eprintln!("the old save is kept in {}", Path::new(&backup).display()),
+            Err(e) => eprintln!("couldn't back up save file {}: {e}", self.path.display()),
+        }
+    }
+
+    /// Writes the save if the cartridge RAM changed since
+    /// the last write.
+    pub fn write_if_dirty(&self, gb: &mut Gb) {
+        if !gb.cartridge_has_battery() || !gb.cartridge_ram_dirty() {
+            return;
+        }
+
+        match self.write(gb) {
+            Ok(()) => gb.mark_cartridge_ram_saved(),
+            Err(e) => eprintln!("couldn't write save file {}: {e}", self.path.display()),
+        }
+    }
+
+    // the save is written to a temporary file first, so a
+    // crash while writing doesn't lose the previous one
+    fn write(&self, gb: &Gb) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(gb.cartridge_ram())?;
+        if let Some(trailer) = gb.cartridge_rtc_trailer(unix_time()) {
+            file.write_all(&trailer)?;
+        }
+        file.sync_all()?;
+
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
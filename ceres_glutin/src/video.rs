@@ -20,19 +20,23 @@ pub struct Renderer {
     vao: NativeVertexArray,
     texture: NativeTexture,
     uniform_loc: UniformLocation,
+    // texture width, wider with many screens side by side
+    width: u32,
 }
 
 impl Renderer {
-    pub fn init(event_loop: &EventLoop<()>) -> Self {
+    pub fn init(event_loop: &EventLoop<()>, title: &str, screens: u32) -> Self {
+        let width = PX_WIDTH * screens;
+
         unsafe {
             let window_builder = WindowBuilder::new()
-                .with_title(super::CERES_STR)
+                .with_title(title)
                 .with_inner_size(PhysicalSize {
-                    width: PX_WIDTH as i32 * 4,
+                    width: width as i32 * 4,
                     height: PX_HEIGHT as i32 * 4,
                 })
                 .with_min_inner_size(PhysicalSize {
-                    width: PX_WIDTH as i32,
+                    width: width as i32,
                     height: PX_HEIGHT as i32,
                 });
 
@@ -115,9 +119,10 @@ impl Renderer {
                 vao,
                 texture,
                 uniform_loc,
+                width,
             };
 
-            res.resize(PX_WIDTH * MUL, PX_HEIGHT * MUL);
+            res.resize(width * MUL, PX_HEIGHT * MUL);
 
             res
         }
@@ -138,6 +143,10 @@ impl Renderer {
         self.resize(size.width, size.height);
     }
 
+    pub fn window_size(&self) -> PhysicalSize<u32> {
+        self.ctx_wrapper.window().inner_size()
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         unsafe {
             self.gl.viewport(0, 0, width as i32, height as i32);
@@ -145,8 +154,8 @@ impl Renderer {
 
             self.gl.use_program(Some(self.program));
 
-            let mul = min(width / PX_WIDTH, height / PX_HEIGHT);
-            let x = (PX_WIDTH * mul) as f32 / width as f32;
+            let mul = min(width / self.width, height / PX_HEIGHT);
+            let x = (self.width * mul) as f32 / width as f32;
             let y = (PX_HEIGHT * mul) as f32 / height as f32;
 
             self.gl.uniform_2_f32(Some(&self.uniform_loc), x, y);
@@ -161,7 +170,7 @@ impl Renderer {
                 glow::TEXTURE_2D,
                 0,
                 glow::RGBA as i32,
-                PX_WIDTH as i32,
+                self.width as i32,
                 PX_HEIGHT as i32,
                 0,
                 glow::RGBA,
@@ -0,0 +1,272 @@
+#![warn(
+    clippy::pedantic,
+    clippy::as_underscore,
+    clippy::clone_on_ref_ptr,
+    clippy::decimal_literal_representation,
+    clippy::deref_by_slicing,
+    clippy::empty_drop,
+    clippy::empty_structs_with_brackets,
+    clippy::float_cmp_const,
+    clippy::fn_to_numeric_cast_any,
+    clippy::get_unwrap,
+    clippy::if_then_some_else_none,
+    clippy::let_underscore_must_use,
+    clippy::lossy_float_literal,
+    clippy::map_err_ignore,
+    clippy::mem_forget,
+    clippy::mixed_read_write_in_expression,
+    clippy::modulo_arithmetic,
+    clippy::non_ascii_literal,
+    clippy::rc_buffer,
+    clippy::rc_mutex,
+    clippy::rest_pat_in_fully_bound_structs,
+    clippy::same_name_method,
+    clippy::self_named_module_files,
+    clippy::shadow_unrelated,
+    clippy::str_to_string,
+    clippy::string_add,
+    clippy::string_slice,
+    clippy::try_err,
+    clippy::unnecessary_self_imports,
+    clippy::unneeded_field_pattern
+)]
+#![allow(
+    clippy::cast_lossless,
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_possible_wrap
+)]
+
+use {
+    ceres_core::{Cartridge, CartridgeHeader, Gb, Model, Printer, Sample, TestResult},
+    ceres_png::{read_still_image, write_printout},
+    clap::{ArgEnum, Parser},
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+mod script;
+
+const SAMPLE_RATE: u32 = 48000;
+
+// the core only takes function pointers as callbacks
+static SAMPLES: Mutex<Vec<Sample>> = Mutex::new(Vec::new());
+static SERIAL: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Runs a ROM without window or audio device, printing its
+/// serial output to stdout.
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    rom_path: PathBuf,
+
+    #[clap(short, long, arg_enum, default_value = "auto")]
+    model: CliModel,
+
+    /// Maximum number of frames to run
+    #[clap(short, long, default_value_t = 600)]
+    frames: u32,
+
+    /// Stop once the serial output contains this text
+    #[clap(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    until_serial: Option<String>,
+
+    /// Stop once a mooneye or blargg test ROM reports its
+    /// result, exiting with an error if it didn't pass
+    #[clap(long)]
+    test_rom: bool,
+
+    /// Input script, every line is `<frame> <button>
+    /// <press|release>`
+    #[clap(short, long)]
+    input: Option<PathBuf>,
+
+    /// Write the last frame as PNG
+    #[clap(long)]
+    png: Option<PathBuf>,
+
+    /// Print a hash of the last frame to stderr
+    #[clap(long)]
+    hash: bool,
+
+    /// Write the audio output as WAV
+    #[clap(long)]
+    wav: Option<PathBuf>,
+
+    /// Connect a Game Boy Printer, writing printouts to
+    /// this directory as print-1.png, print-2.png...
+    #[clap(long)]
+    printer: Option<PathBuf>,
+
+    /// Make the Game Boy Camera take pictures of this PNG
+    /// image
+    #[clap(long)]
+    camera: Option<PathBuf>,
+}
+
+#[derive(Clone, ArgEnum)]
+enum CliModel {
+    Auto,
+    Dmg,
+    Mgb,
+    Cgb,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let rom =
+        std::fs::read(&cli.rom_path).unwrap_or_else(|e| exit(&format!("couldn't read ROM: {e}")));
+    let cart = Cartridge::new(&rom, None).unwrap_or_else(|e| exit(&format!("invalid ROM: {e:?}")));
+
+    let model = match cli.model {
+        CliModel::Auto => CartridgeHeader::new(&rom).map_or(Model::Cgb, |h| Model::from_header(&h)),
+        CliModel::Dmg => Model::Dmg,
+        CliModel::Mgb => Model::Mgb,
+        CliModel::Cgb => Model::Cgb,
+    };
+
+    let script = cli.input.as_deref().map_or_else(Vec::new, |path| {
+        script::parse(path).unwrap_or_else(|e| exit(&format!("invalid input script: {e}")))
+    });
+
+    let apu_frame_callback = if cli.wav.is_some() {
+        capture_audio
+    } else {
+        discard_audio
+    };
+
+    let mut gb = Gb::new(model, cart, apu_frame_callback, SAMPLE_RATE);
+    gb.set_serial_callback(serial_callback);
+
+    if let Some(path) = &cli.camera {
+        let image = read_still_image(path)
+            .unwrap_or_else(|e| exit(&format!("couldn't read camera image: {e}")));
+        gb.set_camera_source(Box::new(image));
+    }
+
+    let mut printer = cli.printer.as_ref().map(|dir| {
+        let printer = Printer::new();
+        printer.connect(&mut gb);
+        (printer, dir)
+    });
+    let mut printouts = 0;
+
+    let mut events = script.iter().peekable();
+
+    for frame in 0..cli.frames {
+        while let Some(event) = events.next_if(|e| e.frame == frame) {
+            if event.pressed {
+                gb.press(event.button);
+            } else {
+                gb.release(event.button);
+            }
+        }
+
+        gb.run_frame();
+
+        if let Some((printer, dir)) = &mut printer {
+            while let Some(printout) = printer.take_printout() {
+                printouts += 1;
+                let path = dir.join(format!("print-{printouts}.png"));
+                write_printout(&path, &printout)
+                    .unwrap_or_else(|e| exit(&format!("couldn't write printout: {e}")));
+            }
+        }
+
+        if let Some(text) = &cli.until_serial {
+            let serial = SERIAL.lock().unwrap();
+            if serial.windows(text.len()).any(|w| w == text.as_bytes()) {
+                break;
+            }
+        }
+
+        if cli.test_rom && gb.test_result().is_some() {
+            break;
+        }
+    }
+
+    std::io::stdout().flush().ok();
+
+    if let Some(path) = &cli.png {
+        write_png(path, gb.pixel_data())
+            .unwrap_or_else(|e| exit(&format!("couldn't write PNG: {e}")));
+    }
+
+    if cli.hash {
+        eprintln!("{:016x}", frame_hash(gb.pixel_data()));
+    }
+
+    if let Some(path) = &cli.wav {
+        write_wav(path).unwrap_or_else(|e| exit(&format!("couldn't write WAV: {e}")));
+    }
+
+    if cli.test_rom {
+        match gb.test_result() {
+            Some(TestResult::Passed) => eprintln!("test passed"),
+            Some(TestResult::Failed) => exit("test failed"),
+            None => exit("test timed out"),
+        }
+    }
+}
+
+fn exit(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1);
+}
+
+fn capture_audio(l: Sample, r: Sample) {
+    let mut samples = SAMPLES.lock().unwrap();
+    samples.push(l);
+    samples.push(r);
+}
+
+fn discard_audio(_: Sample, _: Sample) {}
+
+fn serial_callback(byte: u8) {
+    SERIAL.lock().unwrap().push(byte);
+    std::io::stdout().write_all(&[byte]).ok();
+}
+
+// FNV-1a, stable between runs and platforms
+fn frame_hash(rgba: &[u8]) -> u64 {
+    rgba.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+fn write_png(path: &Path, rgba: &[u8]) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(
+        file,
+        ceres_core::PX_WIDTH as u32,
+        ceres_core::PX_HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)
+}
+
+fn write_wav(path: &Path) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in SAMPLES.lock().unwrap().iter() {
+        writer.write_sample(sample)?;
+    }
+
+    writer.finalize()
+}
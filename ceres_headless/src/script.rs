@@ -0,0 +1,82 @@
+use {
+    ceres_core::Button,
+    std::{fmt, fs, io, path::Path},
+};
+
+/// A button change at the start of a frame.
+pub struct Event {
+    pub frame: u32,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+pub enum Error {
+    Io(io::Error),
+    Syntax(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Syntax(line) => write!(f, "syntax error at line {line}"),
+        }
+    }
+}
+
+/// Parses an input script, every non empty line has the
+/// form `<frame> <button> <press|release>` and `#` starts a
+/// comment. Events are returned sorted by frame.
+pub fn parse(path: &Path) -> Result<Vec<Event>, Error> {
+    let text = fs::read_to_string(path).map_err(Error::Io)?;
+    let mut events = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+
+        let Some(frame) = words.next() else {
+            continue;
+        };
+
+        let event = parse_event(frame, words.next(), words.next()).ok_or(Error::Syntax(i + 1))?;
+        if words.next().is_some() {
+            return Err(Error::Syntax(i + 1));
+        }
+
+        events.push(event);
+    }
+
+    // stable, keeps the order of events in the same frame
+    events.sort_by_key(|e| e.frame);
+
+    Ok(events)
+}
+
+fn parse_event(frame: &str, button: Option<&str>, action: Option<&str>) -> Option<Event> {
+    let frame = frame.parse().ok()?;
+
+    let button = match button?.to_ascii_lowercase().as_str() {
+        "right" => Button::Right,
+        "left" => Button::Left,
+        "up" => Button::Up,
+        "down" => Button::Down,
+        "a" => Button::A,
+        "b" => Button::B,
+        "select" => Button::Select,
+        "start" => Button::Start,
+        _ => return None,
+    };
+
+    let pressed = match action? {
+        "press" => true,
+        "release" => false,
+        _ => return None,
+    };
+
+    Some(Event {
+        frame,
+        button,
+        pressed,
+    })
+}
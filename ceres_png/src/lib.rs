@@ -0,0 +1,111 @@
+//! PNG images of the Game Boy Camera and Printer, shared by
+//! the frontends.
+
+#![warn(
+    clippy::pedantic,
+    clippy::as_underscore,
+    clippy::clone_on_ref_ptr,
+    clippy::decimal_literal_representation,
+    clippy::deref_by_slicing,
+    clippy::empty_drop,
+    clippy::empty_structs_with_brackets,
+    clippy::float_cmp_const,
+    clippy::fn_to_numeric_cast_any,
+    clippy::get_unwrap,
+    clippy::if_then_some_else_none,
+    clippy::let_underscore_must_use,
+    clippy::lossy_float_literal,
+    clippy::map_err_ignore,
+    clippy::mem_forget,
+    clippy::mixed_read_write_in_expression,
+    clippy::modulo_arithmetic,
+    clippy::non_ascii_literal,
+    clippy::rc_buffer,
+    clippy::rc_mutex,
+    clippy::rest_pat_in_fully_bound_structs,
+    clippy::same_name_method,
+    clippy::self_named_module_files,
+    clippy::shadow_unrelated,
+    clippy::str_to_string,
+    clippy::string_add,
+    clippy::string_slice,
+    clippy::try_err,
+    clippy::unnecessary_self_imports,
+    clippy::unneeded_field_pattern
+)]
+
+use {
+    ceres_core::{Printout, StillImage},
+    std::{
+        fs::File,
+        io::{BufReader, BufWriter},
+        path::Path,
+    },
+};
+
+/// Reads a PNG image for the Pocket Camera to take pictures
+/// of.
+///
+/// # Errors
+///
+/// Will return `Err` if the file can't be read or isn't a
+/// valid PNG.
+pub fn read_still_image(path: &Path) -> Result<StillImage, png::DecodingError> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size().unwrap_or(0)];
+    let info = reader.next_frame(&mut buf)?;
+
+    let gray: Vec<u8> = buf[..info.buffer_size()]
+        .chunks_exact(info.color_type.samples())
+        .map(|px| match *px {
+            // the alpha channel is ignored
+            [r, g, b, ..] => luma(r, g, b),
+            [v, ..] => v,
+            [] => 0,
+        })
+        .collect();
+
+    Ok(StillImage::new(
+        info.width as usize,
+        info.height as usize,
+        &gray,
+    ))
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    let luma = (u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000;
+    u8::try_from(luma).unwrap_or(u8::MAX)
+}
+
+/// Writes a printout of the Game Boy Printer as a grayscale
+/// PNG.
+///
+/// # Errors
+///
+/// Will return `Err` if the file can't be written.
+pub fn write_printout(path: &Path, printout: &Printout) -> Result<(), png::EncodingError> {
+    const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+    let file = BufWriter::new(File::create(path)?);
+
+    // printouts are 160 pixels wide and at most a few
+    // thousand tall
+    let width = u32::try_from(printout.width()).unwrap_or(u32::MAX);
+    let height = u32::try_from(printout.height()).unwrap_or(u32::MAX);
+
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let gray: Vec<u8> = printout
+        .pixels()
+        .iter()
+        .map(|&shade| SHADES[usize::from(shade)])
+        .collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&gray)
+}